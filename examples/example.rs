@@ -1,14 +1,5 @@
-use fwupd_dbus::{Client, Signal};
-use std::{
-    error::Error,
-    process,
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
-    },
-    thread,
-    time::Duration,
-};
+use fwupd_dbus::{Cancellable, Client, Signal};
+use std::{error::Error, process, thread, time::Duration};
 
 fn main() {
     if let Err(why) = main_() {
@@ -25,11 +16,8 @@ fn main() {
 }
 
 fn main_() -> Result<(), Box<dyn Error>> {
-    // Atomic value used to stop the background thread.
-    let cancellable = Arc::new(AtomicBool::new(true));
-
     // Begin listening to signals in the background
-    listen_in_background(cancellable.clone());
+    listen_in_background(Cancellable::new());
 
     // Create a new dbus client connection.
     let fwupd = &Client::new()?;
@@ -77,24 +65,20 @@ fn main_() -> Result<(), Box<dyn Error>> {
     for remote in fwupd.remotes()? {
         println!("{:#?}", remote);
 
-        remote.update_metadata(fwupd)?;
+        remote.update_metadata(fwupd, None)?;
     }
 
+    // Keep reporting signals from the daemon until the process is interrupted.
     loop {
-        std::thread::sleep(Duration::from_secs(1));
+        thread::sleep(Duration::from_secs(1));
     }
-
-    // Stop listening to signals in the background.
-    cancellable.store(true, Ordering::SeqCst);
-
-    Ok(())
 }
 
-fn listen_in_background(cancellable: Arc<AtomicBool>) {
+fn listen_in_background(cancellable: Cancellable) {
     thread::spawn(move || {
         if let Ok(fwupd) = Client::new() {
             // Listen for signals received by the daemon.
-            let signals = fwupd.listen_signals(cancellable).unwrap();
+            let signals = fwupd.listen_signals(&cancellable).unwrap();
             for signal in signals {
                 match signal {
                     Signal::Changed => {
@@ -107,7 +91,7 @@ fn listen_in_background(cancellable: Arc<AtomicBool>) {
                         println!("device changed: {:?}", device);
                     }
                     Signal::DeviceRemoved(device) => {
-                        println!("device added: {:?}", device);
+                        println!("device removed: {:?}", device);
                     }
                     Signal::DeviceRequest(request) => {
                         println!("device request: {:?}", request);
@@ -0,0 +1,497 @@
+use crate::{
+    add_release_checksum, bios::validate_bios_settings, checksums_from_message, dicts_from_message,
+    install_options, method_error, property_error, signal_rule, with_timeout, BiosSetting,
+    Branches, Cancellable, DBusDict, Device, DeviceId, DeviceTree, DynVariant, Error, FeatureFlags,
+    InstallFlags, Plugin, Release, Remote, RemoteId, SecurityAttr, SecurityEvent, Signal, Status,
+    DBUS_IFACE, DBUS_NAME, DBUS_PATH,
+};
+use futures_util::{future, Stream, StreamExt};
+use std::{
+    collections::HashMap,
+    fs::OpenOptions,
+    future::Future,
+    os::unix::io::{FromRawFd, IntoRawFd, OwnedFd},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use zbus::{
+    export::serde::Serialize,
+    zvariant::{DynamicType, Fd, OwnedValue},
+    CacheProperties, Connection, ConnectionBuilder, Message, MessageStream, Proxy, ProxyBuilder,
+};
+
+/// An asynchronous DBus client for interacting with the fwupd daemon.
+///
+/// Provides the same daemon methods as [`Client`](crate::Client), without blocking the executor
+/// while the daemon works.
+pub struct AsyncClient {
+    proxy:    Proxy<'static>,
+    timeout:  Option<Duration>,
+    timeouts: HashMap<&'static str, Option<Duration>>,
+}
+
+impl AsyncClient {
+    pub async fn new() -> Result<Self, Error> {
+        let connection = Connection::system().await.map_err(Error::Connection)?;
+        Self::from_connection(connection).await
+    }
+
+    /// Creates a client for a fwupd daemon on the bus at the given address.
+    pub async fn from_address(address: &str) -> Result<Self, Error> {
+        let connection = ConnectionBuilder::address(address)
+            .map_err(Error::Connection)?
+            .build()
+            .await
+            .map_err(Error::Connection)?;
+
+        Self::from_connection(connection).await
+    }
+
+    /// Creates a client from an existing connection to the bus that fwupd is on.
+    pub async fn from_connection(connection: Connection) -> Result<Self, Error> {
+        let builder = ProxyBuilder::new_bare(&connection)
+            .destination(DBUS_NAME)
+            .and_then(|builder| builder.path(DBUS_PATH))
+            .and_then(|builder| builder.interface(DBUS_IFACE))
+            .map_err(Error::Connection)?;
+
+        let proxy = builder
+            .cache_properties(CacheProperties::No)
+            .build()
+            .await
+            .map_err(Error::Connection)?;
+
+        Ok(Self { proxy, timeout: None, timeouts: HashMap::new() })
+    }
+
+    /// Sets the timeout of a specific method of the daemon, overriding the default timeout.
+    ///
+    /// A timeout of `None` waits for the method to return, however long it takes.
+    pub fn method_timeout(mut self, method: &'static str, timeout: Option<Duration>) -> Self {
+        self.timeouts.insert(method, timeout);
+        self
+    }
+
+    /// Sets the default timeout of method calls and property reads of the daemon.
+    ///
+    /// By default, the client waits for the daemon to respond, however long it takes.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Activate a firmware update on the device.
+    pub async fn activate<D: AsRef<DeviceId>>(&self, id: D) -> Result<(), Error> {
+        self.action_method("Activate", id.as_ref().as_ref()).await
+    }
+
+    /// Adds the best checksum of the release to the approved firmware of the daemon.
+    pub async fn approve_release(&self, release: &Release) -> Result<(), Error> {
+        let mut checksums = self.approved_firmware().await?;
+        if add_release_checksum(&mut checksums, release)? {
+            self.set_approved_firmware(&checksums).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Gets the checksums of the firmware that the daemon is allowed to install.
+    pub async fn approved_firmware(&self) -> Result<Vec<Box<str>>, Error> {
+        self.checksums_method("GetApprovedFirmware").await
+    }
+
+    /// Adds the best checksum of the release to the blocked firmware of the daemon.
+    pub async fn block_release(&self, release: &Release) -> Result<(), Error> {
+        let mut checksums = self.blocked_firmware().await?;
+        if add_release_checksum(&mut checksums, release)? {
+            self.set_blocked_firmware(&checksums).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Gets the BIOS settings of the system.
+    pub async fn bios_settings(&self) -> Result<Vec<BiosSetting>, Error> {
+        self.get_method("GetBiosSettings", &()).await
+    }
+
+    /// Gets the checksums of the firmware that the daemon refuses to install.
+    pub async fn blocked_firmware(&self) -> Result<Vec<Box<str>>, Error> {
+        self.checksums_method("GetBlockedFirmware").await
+    }
+
+    /// Gets the releases of a device grouped by their branch, newest first.
+    pub async fn branches(&self, device: &Device) -> Result<Branches, Error> {
+        let releases = self.releases(device).await?;
+        Ok(crate::release::group_by_branch(device, releases))
+    }
+
+    /// Clears the results of an offline update.
+    pub async fn clear_results<D: AsRef<DeviceId>>(&self, id: D) -> Result<(), Error> {
+        self.action_method("ClearResults", id.as_ref().as_ref()).await
+    }
+
+    /// The version of this daemon.
+    pub async fn daemon_version(&self) -> Result<Box<str>, Error> {
+        self.get_property::<String>("DaemonVersion").await.map(Box::from)
+    }
+
+    /// Gets details about a local firmware file.
+    pub async fn details<H: IntoRawFd>(
+        &self,
+        handle: H,
+    ) -> Result<Vec<HashMap<String, DynVariant>>, Error> {
+        self.get_handle_method("GetDetails", handle).await
+    }
+
+    /// Gets a list of all the devices that are supported.
+    pub async fn devices(&self) -> Result<Vec<Device>, Error> {
+        self.get_method("GetDevices", &()).await
+    }
+
+    /// Gets the devices of the daemon, arranged by their parents.
+    pub async fn device_tree(&self) -> Result<DeviceTree, Error> {
+        self.devices().await.map(DeviceTree::new)
+    }
+
+    /// Fixes the host security attribute with the given AppStream ID.
+    ///
+    /// Fails with [`Error::NotSupported`] if the attribute cannot be fixed, or
+    /// [`Error::AuthFailed`] if the caller is not authorized to change it.
+    pub async fn fix_host_security_attr(&self, appstream_id: &str) -> Result<(), Error> {
+        self.action_method("FixHostSecurityAttr", appstream_id).await
+    }
+
+    /// Gets the host security attributes of the system.
+    pub async fn host_security_attrs(&self) -> Result<Vec<SecurityAttr>, Error> {
+        self.get_method("GetHostSecurityAttrs", &()).await
+    }
+
+    /// Gets the most recent changes to the host security attributes, up to `limit` events.
+    pub async fn host_security_events(&self, limit: u32) -> Result<Vec<SecurityEvent>, Error> {
+        self.get_method("GetHostSecurityEvents", &(limit,)).await
+    }
+
+    /// The Host Security ID of the system, as calculated by the daemon, e.g. `HSI:2 (v1.8.4)`.
+    pub async fn host_security_id(&self) -> Result<Box<str>, Error> {
+        self.get_property::<String>("HostSecurityId").await.map(Box::from)
+    }
+
+    /// Get a list of all the downgrades possible for a specific device.
+    pub async fn downgrades<D: AsRef<DeviceId>>(
+        &self,
+        device_id: D,
+    ) -> Result<Vec<Release>, Error> {
+        let device_id: &str = device_id.as_ref().as_ref();
+        self.get_method("GetDowngrades", &(device_id,)).await
+    }
+
+    /// Gets a list of all the past firmware updates.
+    pub async fn history<H: IntoRawFd>(&self, handle: H) -> Result<Vec<Device>, Error> {
+        self.get_handle_method("GetHistory", handle).await
+    }
+
+    /// Schedules a firmware to be installed.
+    pub async fn install<D: AsRef<DeviceId>, H: IntoRawFd>(
+        &self,
+        id: D,
+        reason: &str,
+        filename: &Path,
+        handle: Option<H>,
+        flags: InstallFlags,
+    ) -> Result<(), Error> {
+        let handle = match handle {
+            Some(handle) => unsafe { OwnedFd::from_raw_fd(handle.into_raw_fd()) },
+            None => {
+                OpenOptions::new().read(true).open(filename).map_err(Error::FirmwareOpen)?.into()
+            }
+        };
+
+        let id: &str = id.as_ref().as_ref();
+        let options = install_options(reason, filename, flags);
+
+        self.call_method("Install", &(id, Fd::from(&handle), options)).await?;
+        Ok(())
+    }
+
+    /// Modifies a device in some way.
+    pub async fn modify_device<D: AsRef<DeviceId>>(
+        &self,
+        device_id: D,
+        key: &str,
+        value: &str,
+    ) -> Result<(), Error> {
+        let device_id: &str = device_id.as_ref().as_ref();
+        self.call_method("ModifyDevice", &(device_id, key, value)).await?;
+        Ok(())
+    }
+
+    /// Modifies a remote in some way.
+    pub async fn modify_remote<R: AsRef<RemoteId>>(
+        &self,
+        remote_id: R,
+        key: &str,
+        value: &str,
+    ) -> Result<(), Error> {
+        let remote_id: &str = remote_id.as_ref().as_ref();
+        self.call_method("ModifyRemote", &(remote_id, key, value)).await?;
+        Ok(())
+    }
+
+    /// The job percentage completion, or 0 for unknown.
+    pub async fn percentage(&self) -> Result<u8, Error> {
+        self.get_property::<u32>("Percentage").await.map(|v| v as u8)
+    }
+
+    /// Receives signals from the DBus daemon.
+    ///
+    /// The stream ends at the first signal received after the `cancellable` is cancelled.
+    pub async fn receive_signals<'a>(
+        &'a self,
+        cancellable: &'a Cancellable,
+    ) -> Result<impl Stream<Item = Signal> + 'a, Error> {
+        let rule = signal_rule().map_err(Error::AddMatch)?;
+        let stream = MessageStream::for_match_rule(rule, self.proxy.connection(), None)
+            .await
+            .map_err(Error::AddMatch)?;
+
+        let stream = stream.take_while(move |_| future::ready(!cancellable.is_cancelled()));
+
+        Ok(stream.filter_map(|message| {
+            let signal = message
+                .map_err(Error::Connection)
+                .and_then(|message| Signal::from_message(&message));
+
+            future::ready(match signal {
+                Ok(signal) => signal,
+                Err(why) => {
+                    eprintln!("signal error: {}", why);
+                    None
+                }
+            })
+        }))
+    }
+
+    /// Gets the plugins of the daemon, and their state.
+    pub async fn plugins(&self) -> Result<Vec<Plugin>, Error> {
+        self.get_method("GetPlugins", &()).await
+    }
+
+    /// Gets a list of all the releases for a specific device.
+    pub async fn releases<D: AsRef<DeviceId>>(&self, device_id: D) -> Result<Vec<Release>, Error> {
+        let device_id: &str = device_id.as_ref().as_ref();
+        self.get_method("GetReleases", &(device_id,)).await
+    }
+
+    /// Find the remote with the given ID.
+    pub async fn remote<D: AsRef<RemoteId>>(&self, id: D) -> Result<Remote, Error> {
+        self.remotes()
+            .await?
+            .into_iter()
+            .find(|remote| &remote.remote_id == id.as_ref())
+            .ok_or(Error::RemoteNotFound)
+    }
+
+    /// Gets the list of remotes.
+    pub async fn remotes(&self) -> Result<Vec<Remote>, Error> {
+        self.get_method("GetRemotes", &()).await
+    }
+
+    /// Gets the results of an offline update.
+    pub async fn results<D: AsRef<DeviceId>>(&self, id: D) -> Result<Option<Device>, Error> {
+        let id: &str = id.as_ref().as_ref();
+        let message = self.call_method("GetResults", &(id,)).await?;
+        let dict: DBusDict =
+            message.body().map_err(|why| Error::ArgumentMismatch("GetResults", why))?;
+        Device::try_from(dict).map(Some)
+    }
+
+    /// Sets the checksums of the firmware that the daemon is allowed to install.
+    pub async fn set_approved_firmware<S: AsRef<str>>(&self, checksums: &[S]) -> Result<(), Error> {
+        let checksums: Vec<&str> = checksums.iter().map(AsRef::as_ref).collect();
+        self.call_method("SetApprovedFirmware", &(checksums,)).await?;
+        Ok(())
+    }
+
+    /// Changes BIOS settings of the system, which are identified by their ID or name.
+    ///
+    /// Every value is validated against the constraints of its setting before any are sent.
+    pub async fn set_bios_settings<K, V>(&self, settings: &HashMap<K, V>) -> Result<(), Error>
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let available = self.bios_settings().await?;
+        let settings = validate_bios_settings(&available, settings)?;
+        let settings: HashMap<&str, &str> =
+            settings.iter().map(|(id, value)| (*id, value.as_ref())).collect();
+
+        self.call_method("SetBiosSettings", &(settings,)).await?;
+        Ok(())
+    }
+
+    /// Sets the checksums of the firmware that the daemon refuses to install.
+    pub async fn set_blocked_firmware<S: AsRef<str>>(&self, checksums: &[S]) -> Result<(), Error> {
+        let checksums: Vec<&str> = checksums.iter().map(AsRef::as_ref).collect();
+        self.call_method("SetBlockedFirmware", &(checksums,)).await?;
+        Ok(())
+    }
+
+    /// Instructs the daemon about which features this client supports.
+    pub async fn set_feature_flags(&self, feature_flags: FeatureFlags) -> Result<(), Error> {
+        self.call_method("SetFeatureFlags", &(feature_flags.bits(),)).await?;
+        Ok(())
+    }
+
+    /// The daemon status, e.g. `Decompressing`.
+    pub async fn status(&self) -> Result<Status, Error> {
+        self.get_property::<u32>("Status").await.map(Status::from)
+    }
+
+    /// Switches a device to another branch of its firmware, by installing the newest release
+    /// on that branch. The default branch is selected with `None`.
+    ///
+    /// This client does not download firmware, so the firmware of the release is obtained from
+    /// `fetch`, e.g. with
+    /// [`Client::fetch_firmware_from_release`](crate::Client::fetch_firmware_from_release) on a
+    /// thread which may block. The release is installed with `ALLOW_BRANCH_SWITCH`,
+    /// `ALLOW_OLDER` and `ALLOW_REINSTALL`, and returned.
+    pub async fn switch_branch<F, Fut, H>(
+        &self,
+        device: &Device,
+        branch: Option<&str>,
+        fetch: F,
+    ) -> Result<Release, Error>
+    where
+        F: FnOnce(&Release) -> Fut,
+        Fut: Future<Output = Result<(PathBuf, Option<H>), Error>>,
+        H: IntoRawFd,
+    {
+        let name = || Box::from(branch.unwrap_or(crate::release::DEFAULT_BRANCH));
+
+        if device.branch.as_deref() == branch {
+            return Err(Error::BranchAlreadySelected(name()));
+        }
+
+        let release = self
+            .branches(device)
+            .await?
+            .remove(&branch.map(Box::from))
+            .and_then(|releases| releases.into_iter().next())
+            .ok_or_else(|| Error::BranchNotFound(name()))?;
+
+        let flags = InstallFlags::ALLOW_BRANCH_SWITCH
+            | InstallFlags::ALLOW_OLDER
+            | InstallFlags::ALLOW_REINSTALL;
+
+        let (filename, handle) = fetch(&release).await?;
+        self.install(device, "(user)", &filename, handle, flags).await?;
+        Ok(release)
+    }
+
+    /// If the daemon has been tainted with a third party plugin.
+    pub async fn tainted(&self) -> Result<bool, Error> {
+        self.get_property::<bool>("Tainted").await
+    }
+
+    /// Unlock the device to allow firmware access.
+    pub async fn unlock<D: AsRef<DeviceId>>(&self, id: D) -> Result<(), Error> {
+        self.action_method("Unlock", id.as_ref().as_ref()).await
+    }
+
+    /// Undoes a fix of the host security attribute with the given AppStream ID.
+    ///
+    /// Fails with [`Error::NotSupported`] if the fix cannot be undone, or
+    /// [`Error::AuthFailed`] if the caller is not authorized to change it.
+    pub async fn undo_host_security_attr(&self, appstream_id: &str) -> Result<(), Error> {
+        self.action_method("UndoHostSecurityAttr", appstream_id).await
+    }
+
+    /// Adds AppStream resource information from a session client.
+    pub async fn update_metadata<D: IntoRawFd, S: IntoRawFd, R: AsRef<RemoteId>>(
+        &self,
+        remote_id: R,
+        data: D,
+        signature: S,
+    ) -> Result<(), Error> {
+        let remote_id: &str = remote_id.as_ref().as_ref();
+        let data = unsafe { OwnedFd::from_raw_fd(data.into_raw_fd()) };
+        let signature = unsafe { OwnedFd::from_raw_fd(signature.into_raw_fd()) };
+
+        self.call_method("UpdateMetadata", &(remote_id, Fd::from(&data), Fd::from(&signature)))
+            .await?;
+        Ok(())
+    }
+
+    /// Get a list of all the upgrades possible for a specific device.
+    pub async fn upgrades<D: AsRef<DeviceId>>(&self, device_id: D) -> Result<Vec<Release>, Error> {
+        let device_id: &str = device_id.as_ref().as_ref();
+        self.get_method("GetUpgrades", &(device_id,)).await
+    }
+
+    /// Verifies firmware on a device by reading it back and performing
+    /// a cryptographic hash, typically SHA1.
+    pub async fn verify<D: AsRef<DeviceId>>(&self, id: D) -> Result<(), Error> {
+        self.action_method("Verify", id.as_ref().as_ref()).await
+    }
+
+    /// Updates the cryptographic hash stored for a device.
+    pub async fn verify_update<D: AsRef<DeviceId>>(&self, id: D) -> Result<(), Error> {
+        self.action_method("VerifyUpdate", id.as_ref().as_ref()).await
+    }
+
+    async fn action_method(&self, method: &'static str, id: &str) -> Result<(), Error> {
+        self.call_method(method, &(id,)).await?;
+        Ok(())
+    }
+
+    async fn get_method<T, B>(&self, method: &'static str, body: &B) -> Result<Vec<T>, Error>
+    where
+        T: TryFrom<DBusDict>,
+        Error: From<T::Error>,
+        B: Serialize + DynamicType,
+    {
+        let message = self.call_method(method, body).await?;
+        dicts_from_message(method, &message)
+    }
+
+    async fn get_handle_method<T, H>(
+        &self,
+        method: &'static str,
+        handle: H,
+    ) -> Result<Vec<T>, Error>
+    where
+        T: TryFrom<DBusDict>,
+        Error: From<T::Error>,
+        H: IntoRawFd,
+    {
+        let handle = unsafe { OwnedFd::from_raw_fd(handle.into_raw_fd()) };
+        self.get_method(method, &(Fd::from(&handle),)).await
+    }
+
+    async fn checksums_method(&self, method: &'static str) -> Result<Vec<Box<str>>, Error> {
+        let message = self.call_method(method, &()).await?;
+        checksums_from_message(method, &message)
+    }
+
+    async fn get_property<T>(&self, property: &'static str) -> Result<T, Error>
+    where
+        T: TryFrom<OwnedValue>,
+        T::Error: Into<zbus::Error>,
+    {
+        with_timeout(self.timeout, self.proxy.get_property::<T>(property))
+            .await
+            .map_err(|why| property_error(property, why))
+    }
+
+    async fn call_method<B>(&self, method: &'static str, body: &B) -> Result<Arc<Message>, Error>
+    where
+        B: Serialize + DynamicType,
+    {
+        let timeout = self.timeouts.get(method).copied().unwrap_or(self.timeout);
+        with_timeout(timeout, self.proxy.call_method(method, body))
+            .await
+            .map_err(|why| method_error(method, why))
+    }
+}
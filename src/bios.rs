@@ -0,0 +1,191 @@
+use crate::{common::*, dbus_helpers::*, DBusDict};
+use std::{borrow::Cow, collections::HashMap, fmt};
+
+/// The kind of value that a BIOS setting holds.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BiosSettingKind {
+    /// A kind which is unknown, or too new for this crate, with its raw value.
+    Unknown(u32),
+    /// One of the possible values of the setting
+    Enumeration,
+    /// An integer between the bounds of the setting
+    Integer,
+    /// A string with a length between the bounds of the setting
+    String,
+}
+
+impl From<u32> for BiosSettingKind {
+    fn from(value: u32) -> Self {
+        use self::BiosSettingKind::*;
+        match value {
+            1 => Enumeration,
+            2 => Integer,
+            3 => String,
+            _ => Unknown(value),
+        }
+    }
+}
+
+impl Default for BiosSettingKind {
+    fn default() -> Self { BiosSettingKind::Unknown(0) }
+}
+
+impl fmt::Display for BiosSettingKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::BiosSettingKind::*;
+        let name = match *self {
+            Unknown(value) => return fmt_unknown(value, f),
+            Enumeration => "enumeration",
+            Integer => "integer",
+            String => "string",
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// An error that may occur when validating the value of a BIOS setting.
+#[derive(Debug, Error)]
+pub enum BiosSettingError {
+    #[error("{} is not an integer", _0)]
+    NotInteger(Box<str>),
+    #[error("{} is not a multiple of {}", _0, _1)]
+    NotIncrement(u64, u64),
+    #[error("no such BIOS setting")]
+    NotFound,
+    #[error("{} is not one of the possible values", _0)]
+    NotPossible(Box<str>),
+    #[error("{} is not between {} and {}", _0, _1, _2)]
+    OutOfBounds(u64, u64, u64),
+    #[error("setting is read-only")]
+    ReadOnly,
+}
+
+/// A firmware setting of the system, which may be changed through the daemon.
+#[derive(Clone, Debug, Default)]
+pub struct BiosSetting {
+    pub current_value:    Box<str>,
+    pub description:      Option<Box<str>>,
+    pub filename:         Option<Box<str>>,
+    pub id:               Box<str>,
+    pub kind:             BiosSettingKind,
+    pub lower_bound:      u64,
+    pub name:             Box<str>,
+    pub possible_values:  Box<[Box<str>]>,
+    pub read_only:        bool,
+    pub scalar_increment: u64,
+    pub upper_bound:      u64,
+}
+
+impl BiosSetting {
+    /// Checks that a value can be assigned to this setting.
+    ///
+    /// Returns the value as the daemon expects it, which may differ from the given value
+    /// in the case of possible values of an enumeration.
+    pub fn validate<'a>(&'a self, value: &'a str) -> Result<Cow<'a, str>, BiosSettingError> {
+        if self.read_only {
+            return Err(BiosSettingError::ReadOnly);
+        }
+
+        match self.kind {
+            BiosSettingKind::Enumeration => self
+                .possible_values
+                .iter()
+                .find(|possible| possible.eq_ignore_ascii_case(value))
+                .map(|possible| Cow::Borrowed(possible.as_ref()))
+                .ok_or_else(|| BiosSettingError::NotPossible(value.into())),
+            BiosSettingKind::Integer => {
+                let integer: u64 =
+                    value.parse().map_err(|_| BiosSettingError::NotInteger(value.into()))?;
+
+                self.check_bounds(integer)?;
+
+                if self.scalar_increment != 0 && integer % self.scalar_increment != 0 {
+                    return Err(BiosSettingError::NotIncrement(integer, self.scalar_increment));
+                }
+
+                Ok(Cow::Borrowed(value))
+            }
+            BiosSettingKind::String => {
+                self.check_bounds(value.len() as u64)?;
+                Ok(Cow::Borrowed(value))
+            }
+            BiosSettingKind::Unknown(_) => Ok(Cow::Borrowed(value)),
+        }
+    }
+
+    fn check_bounds(&self, value: u64) -> Result<(), BiosSettingError> {
+        if value < self.lower_bound || value > self.upper_bound {
+            return Err(BiosSettingError::OutOfBounds(value, self.lower_bound, self.upper_bound));
+        }
+
+        Ok(())
+    }
+}
+
+impl TryFrom<DBusDict> for BiosSetting {
+    type Error = crate::Error;
+
+    fn try_from(dict: DBusDict) -> Result<Self, crate::Error> {
+        let mut setting = BiosSetting::default();
+
+        for (key, value) in dict {
+            let key = key.as_str();
+            match key {
+                "BiosSettingCurrentValue" => setting.current_value = dbus_str(&value, key)?.into(),
+                "BiosSettingId" => setting.id = dbus_str(&value, key)?.into(),
+                "BiosSettingLowerBound" => setting.lower_bound = dbus_u64(&value, key)?,
+                "BiosSettingPossibleValues" => {
+                    setting.possible_values = dbus_str_array(&value, key)?
+                }
+                "BiosSettingReadOnly" => setting.read_only = dbus_u64(&value, key)? != 0,
+                "BiosSettingScalarIncrement" => setting.scalar_increment = dbus_u64(&value, key)?,
+                "BiosSettingType" => {
+                    setting.kind = BiosSettingKind::from(dbus_u64(&value, key)? as u32)
+                }
+                "BiosSettingUpperBound" => setting.upper_bound = dbus_u64(&value, key)?,
+                KEY_DESCRIPTION => setting.description = Some(dbus_str(&value, key)?.into()),
+                KEY_FILENAME => setting.filename = Some(dbus_str(&value, key)?.into()),
+                KEY_NAME => setting.name = dbus_str(&value, key)?.into(),
+                other => {
+                    eprintln!(
+                        "unknown BIOS setting key: {} ({}): {:?}",
+                        other,
+                        value.value_signature(),
+                        value
+                    );
+                }
+            }
+        }
+
+        Ok(setting)
+    }
+}
+
+/// Validates new values for the BIOS settings, which are identified by their ID or name.
+///
+/// Returns the values to send to the daemon, keyed by the IDs of their settings.
+pub(crate) fn validate_bios_settings<'a, K, V>(
+    available: &'a [BiosSetting],
+    settings: &'a HashMap<K, V>,
+) -> Result<HashMap<&'a str, Cow<'a, str>>, crate::Error>
+where
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    settings
+        .iter()
+        .map(|(key, value)| {
+            let key = key.as_ref();
+            let invalid = |why| crate::Error::BiosSetting(key.into(), why);
+
+            let setting = available
+                .iter()
+                .find(|setting| &*setting.id == key || &*setting.name == key)
+                .ok_or_else(|| invalid(BiosSettingError::NotFound))?;
+
+            let value = setting.validate(value.as_ref()).map_err(invalid)?;
+            Ok((setting.id.as_ref(), value))
+        })
+        .collect()
+}
@@ -0,0 +1,179 @@
+use crate::{Client, Downloader, Error, UreqDownloader, DBUS_IFACE, DBUS_NAME, DBUS_PATH};
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+use zbus::{
+    blocking::{Connection, ConnectionBuilder, ProxyBuilder},
+    CacheProperties,
+};
+
+/// The bus which a client connects to.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub enum Bus {
+    /// The system bus, where the fwupd daemon normally runs.
+    #[default]
+    System,
+    /// The session bus of the current user.
+    Session,
+    /// A bus at an explicit address, such as `unix:path=/run/fwupd-test/bus`.
+    Address(String),
+}
+
+/// Configures how a [`Client`] connects to the daemon and to remotes.
+///
+/// ```no_run
+/// use fwupd_dbus::{Bus, ClientBuilder};
+/// use std::time::Duration;
+///
+/// let client = ClientBuilder::new()
+///     .bus(Bus::Session)
+///     .timeout(Duration::from_secs(25))
+///     .method_timeout("Install", None)
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Default)]
+pub struct ClientBuilder {
+    bus:         Bus,
+    client_name: Option<String>,
+    downloader:  Option<Box<dyn Downloader>>,
+    firmware:    Option<PathBuf>,
+    http:        Option<ureq::Agent>,
+    proxy:       Option<ureq::Proxy>,
+    timeout:     Option<Duration>,
+    timeouts:    HashMap<&'static str, Option<Duration>>,
+    verify:      bool,
+}
+
+impl ClientBuilder {
+    pub fn new() -> Self { Self::default() }
+
+    /// Connects to the bus at the given address, instead of the system bus.
+    pub fn address(self, address: &str) -> Self { self.bus(Bus::Address(address.into())) }
+
+    /// Selects the bus to connect to, which is the system bus by default.
+    pub fn bus(mut self, bus: Bus) -> Self {
+        self.bus = bus;
+        self
+    }
+
+    /// Sets the name of the client, which is also the user agent of its HTTP requests.
+    ///
+    /// Defaults to `fwupd/` followed by the version of the daemon.
+    pub fn client_name(mut self, client_name: impl Into<String>) -> Self {
+        self.client_name = Some(client_name.into());
+        self
+    }
+
+    /// Uses a custom downloader to fetch firmware and metadata from remotes.
+    ///
+    /// The HTTP agent and proxy of the builder are not used with a custom downloader.
+    pub fn downloader(mut self, downloader: impl Downloader + 'static) -> Self {
+        self.downloader = Some(Box::new(downloader));
+        self
+    }
+
+    /// Uses a preconfigured HTTP agent to fetch firmware and metadata from remotes.
+    ///
+    /// The client name and proxy of the builder are not applied to this agent.
+    pub fn http_agent(mut self, agent: ureq::Agent) -> Self {
+        self.http = Some(agent);
+        self
+    }
+
+    /// Downloads firmware into the given directory, rather than the `fwupd-client` directory of
+    /// the XDG cache.
+    pub fn firmware_cache(mut self, directory: impl Into<PathBuf>) -> Self {
+        self.firmware = Some(directory.into());
+        self
+    }
+
+    /// Sets the timeout of a specific method of the daemon, overriding the default timeout.
+    ///
+    /// A timeout of `None` waits for the method to return, however long it takes.
+    pub fn method_timeout(mut self, method: &'static str, timeout: Option<Duration>) -> Self {
+        self.timeouts.insert(method, timeout);
+        self
+    }
+
+    /// Sends HTTP requests to remotes through a proxy.
+    pub fn proxy(mut self, proxy: ureq::Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Sets the default timeout of method calls and property reads of the daemon.
+    ///
+    /// By default, the client waits for the daemon to respond, however long it takes.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Verifies the metadata of remotes against the checksums of their JCAT files, before
+    /// passing the metadata to the daemon.
+    ///
+    /// Remotes with other kinds of keyrings are left for the daemon to verify.
+    pub fn verify_metadata(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Connects to the selected bus and creates the client.
+    pub fn build(self) -> Result<Client, Error> {
+        let connection = match self.bus {
+            Bus::System => Connection::system(),
+            Bus::Session => Connection::session(),
+            Bus::Address(ref address) => {
+                ConnectionBuilder::address(address.as_str()).and_then(ConnectionBuilder::build)
+            }
+        };
+
+        self.build_with_connection(connection.map_err(Error::Connection)?)
+    }
+
+    /// Creates the client from an existing connection, ignoring the selected bus.
+    ///
+    /// Method calls and signal subscriptions of the client will share this connection.
+    pub fn build_with_connection(self, connection: Connection) -> Result<Client, Error> {
+        let proxy = ProxyBuilder::new_bare(&connection)
+            .destination(DBUS_NAME)
+            .and_then(|builder| builder.path(DBUS_PATH))
+            .and_then(|builder| builder.interface(DBUS_IFACE))
+            .and_then(|builder| builder.cache_properties(CacheProperties::No).build())
+            .map_err(Error::Connection)?;
+
+        let mut client = Client {
+            proxy,
+            client_name: String::new(),
+            downloader: Box::new(UreqDownloader::new(ureq::Agent::new())),
+            firmware_cache: self.firmware,
+            timeout: self.timeout,
+            timeouts: self.timeouts,
+            verify_metadata: self.verify,
+        };
+
+        client.client_name = match self.client_name {
+            Some(client_name) => client_name,
+            None => ["fwupd/", &*client.daemon_version()?].concat(),
+        };
+
+        if let Some(downloader) = self.downloader {
+            client.downloader = downloader;
+            return Ok(client);
+        }
+
+        let agent = match self.http {
+            Some(agent) => agent,
+            None => {
+                let builder = ureq::AgentBuilder::new().user_agent(client.client_name.as_str());
+                match self.proxy {
+                    Some(proxy) => builder.proxy(proxy).build(),
+                    None => builder.build(),
+                }
+            }
+        };
+
+        client.downloader = Box::new(UreqDownloader::new(agent));
+
+        Ok(client)
+    }
+}
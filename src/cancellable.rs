@@ -0,0 +1,20 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// A token for cancelling downloads and installs which are in progress.
+///
+/// Clones of a token share its state, so that a token may be cancelled from another thread.
+#[derive(Clone, Debug, Default)]
+pub struct Cancellable(Arc<AtomicBool>);
+
+impl Cancellable {
+    pub fn new() -> Self { Self::default() }
+
+    /// Cancels the operations which were given this token.
+    pub fn cancel(&self) { self.0.store(true, Ordering::SeqCst) }
+
+    /// Checks if the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool { self.0.load(Ordering::SeqCst) }
+}
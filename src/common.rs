@@ -1,6 +1,7 @@
 use crypto_hash::{Algorithm, Hasher};
 use hex_view::HexView;
 use std::{
+    fmt,
     io::{self, Read},
     path::{Path, PathBuf},
 };
@@ -43,7 +44,8 @@ pub fn validate_checksum<R: Read>(data: &mut R, checksum: &str, alg: Algorithm)
     }
 }
 
-pub fn cache_path_from_uri(uri: &Url) -> PathBuf {
+/// The path of the file for `uri`, relative to the cache directory.
+pub fn cache_file_from_uri(uri: &Url) -> PathBuf {
     let domain = uri.host_str();
 
     let path = Path::new(uri.path())
@@ -52,14 +54,10 @@ pub fn cache_path_from_uri(uri: &Url) -> PathBuf {
         .to_str()
         .expect("URI is not UTF-8");
 
-    let maybe_heap: String;
-    cache_path(match domain.as_ref() {
-        Some(domain) => {
-            maybe_heap = [domain, "/", path].concat();
-            Path::new(&maybe_heap)
-        }
-        None => Path::new(path),
-    })
+    match domain {
+        Some(domain) => Path::new(domain).join(path),
+        None => PathBuf::from(path),
+    }
 }
 
 pub fn cache_path(file: &Path) -> PathBuf {
@@ -71,10 +69,13 @@ pub fn cache_path(file: &Path) -> PathBuf {
 
 pub const KEY_APPSTREAM_ID: &str = "AppstreamId"; // s
 pub const KEY_CATEGORIES: &str = "Categories"; // as
+pub const KEY_BRANCH: &str = "Branch"; // s
 pub const KEY_CHECKSUM: &str = "Checksum"; // as
 pub const KEY_CREATED: &str = "Created"; // t
 pub const KEY_DESCRIPTION: &str = "Description"; // s
 pub const KEY_DETAILS_URL: &str = "DetailsUrl"; // s
+pub const KEY_DETACH_CAPTION: &str = "DetachCaption"; // s
+pub const KEY_DETACH_IMAGE: &str = "DetachImage"; // s
 pub const KEY_DEVICE_ID: &str = "DeviceId"; // s
 pub const KEY_FILENAME: &str = "Filename"; // s
 pub const KEY_FLAGS: &str = "Flags"; // t
@@ -84,26 +85,76 @@ pub const KEY_HOMEPAGE: &str = "Homepage"; // s
 pub const KEY_ICON: &str = "Icon"; // as
 pub const KEY_INSTALL_DURATION: &str = "InstallDuration"; // u
 pub const KEY_INSTANCE_IDS: &str = "InstanceIds"; // as
+pub const KEY_ISSUES: &str = "Issues"; // as
 pub const KEY_LICENSE: &str = "License"; // s
+pub const KEY_LOCATIONS: &str = "Locations"; // as
 pub const KEY_METADATA: &str = "Metadata"; // a{ss}
 pub const KEY_MODIFIED: &str = "Modified"; // t
 pub const KEY_NAME: &str = "Name"; // s
 pub const KEY_PARENT_DEVICE_ID: &str = "ParentDeviceId"; // s
+pub const KEY_PERCENTAGE: &str = "Percentage"; // u
 pub const KEY_PLUGIN: &str = "Plugin"; // s
+pub const KEY_PROBLEMS: &str = "Problems"; // t
 pub const KEY_PROTOCOL: &str = "Protocol"; // s
+#[allow(dead_code)]
 pub const KEY_RELEASE: &str = "Release"; // a{sv}
 pub const KEY_REMOTE_ID: &str = "RemoteId"; // s
+pub const KEY_REPORT_URI: &str = "ReportUri"; // s
 pub const KEY_SERIAL: &str = "Serial"; // s
 pub const KEY_SIZE: &str = "Size"; // t
 pub const KEY_SOURCE_URL: &str = "SourceUrl"; // s
+pub const KEY_STATUS: &str = "Status"; // u
 pub const KEY_SUMMARY: &str = "Summary"; // s
+pub const KEY_TAGS: &str = "Tags"; // as
 pub const KEY_TRUST_FLAGS: &str = "TrustFlags"; // t
 pub const KEY_UPDATE_ERROR: &str = "UpdateError"; // s
 pub const KEY_UPDATE_MESSAGE: &str = "UpdateMessage"; // s
 pub const KEY_UPDATE_STATE: &str = "UpdateState"; // u
+pub const KEY_URGENCY: &str = "Urgency"; // u
 pub const KEY_URI: &str = "Uri"; // s
 pub const KEY_VENDOR_ID: &str = "VendorId"; // s
 pub const KEY_VENDOR: &str = "Vendor"; // s
 pub const KEY_VERSION_BOOTLOADER: &str = "VersionBootloader"; // s
+pub const KEY_VERSION_BOOTLOADER_RAW: &str = "VersionBootloaderRaw"; // t
+pub const KEY_VERSION_FORMAT: &str = "VersionFormat"; // u
 pub const KEY_VERSION_LOWEST: &str = "VersionLowest"; // s
+pub const KEY_VERSION_LOWEST_RAW: &str = "VersionLowestRaw"; // t
+pub const KEY_VERSION_RAW: &str = "VersionRaw"; // t
 pub const KEY_VERSION: &str = "Version"; // s
+
+/// Writes the names of flags in the same way as the daemon, such as `updatable|require-ac`,
+/// followed by any bits which are too new to have a name.
+pub fn fmt_flags<F>(flags: &F, f: &mut fmt::Formatter) -> fmt::Result
+where
+    F: bitflags::Flags,
+    F::Bits: Into<u64>,
+{
+    let bits: u64 = flags.bits().into();
+    if bits == 0 {
+        return f.write_str("none");
+    }
+
+    let mut named = 0;
+    for (name, flag) in flags.iter_names() {
+        if named != 0 {
+            f.write_str("|")?;
+        }
+
+        f.write_str(&name.to_ascii_lowercase().replace('_', "-"))?;
+        named |= flag.bits().into();
+    }
+
+    match bits & !named {
+        0 => Ok(()),
+        unnamed if named == 0 => write!(f, "{:#x}", unnamed),
+        unnamed => write!(f, "|{:#x}", unnamed),
+    }
+}
+
+/// Writes a value of an enum which is unknown, keeping its raw value if it is not zero.
+pub fn fmt_unknown(value: u32, f: &mut fmt::Formatter) -> fmt::Result {
+    match value {
+        0 => f.write_str("unknown"),
+        value => write!(f, "unknown({})", value),
+    }
+}
@@ -1,19 +1,53 @@
-use dbus::arg::RefArg;
+use crate::Error;
+use zbus::zvariant::Value;
 
-pub fn dbus_str<'a>(variant: &'a dyn RefArg, kind: &str) -> &'a str {
-    variant
-        .as_str()
-        .unwrap_or_else(|| panic!("expected str for {}, found {}", kind, variant.signature()))
+pub fn dbus_str<'a>(value: &'a Value, key: &str) -> Result<&'a str, Error> {
+    match value {
+        Value::Str(value) => Ok(value.as_str()),
+        Value::Value(value) => dbus_str(value, key),
+        _ => Err(decode_error(value, key, "s")),
+    }
 }
 
-pub fn dbus_u64(variant: &dyn RefArg, kind: &str) -> u64 {
-    variant
-        .as_u64()
-        .unwrap_or_else(|| panic!("expected u64 for {}, found {}", kind, variant.signature()))
+pub fn dbus_u64(value: &Value, key: &str) -> Result<u64, Error> {
+    match *value {
+        Value::Bool(value) => Ok(value as u64),
+        Value::U8(value) => Ok(value as u64),
+        Value::U16(value) => Ok(value as u64),
+        Value::U32(value) => Ok(value as u64),
+        Value::U64(value) => Ok(value),
+        Value::Value(ref value) => dbus_u64(value, key),
+        _ => Err(decode_error(value, key, "t")),
+    }
 }
 
-pub fn dbus_i64(variant: &dyn RefArg, kind: &str) -> i64 {
-    variant
-        .as_i64()
-        .unwrap_or_else(|| panic!("expected i64 for {}, found {}", kind, variant.signature()))
+pub fn dbus_i64(value: &Value, key: &str) -> Result<i64, Error> {
+    match *value {
+        Value::U8(value) => Ok(value as i64),
+        Value::I16(value) => Ok(value as i64),
+        Value::U16(value) => Ok(value as i64),
+        Value::I32(value) => Ok(value as i64),
+        Value::U32(value) => Ok(value as i64),
+        Value::I64(value) => Ok(value),
+        Value::Value(ref value) => dbus_i64(value, key),
+        _ => Err(decode_error(value, key, "x")),
+    }
+}
+
+pub fn dbus_str_array(value: &Value, key: &str) -> Result<Box<[Box<str>]>, Error> {
+    match value {
+        Value::Array(array) => array
+            .iter()
+            .map(|element| match element {
+                Value::Str(element) => Ok(element.as_str().into()),
+                _ => Err(decode_error(value, key, "as")),
+            })
+            .collect(),
+        Value::Value(value) => dbus_str_array(value, key),
+        _ => Err(decode_error(value, key, "as")),
+    }
+}
+
+fn decode_error(value: &Value, key: &str, expected: &'static str) -> Error {
+    Error::Decode { key: key.into(), expected, found: value.value_signature().to_string() }
 }
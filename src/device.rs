@@ -1,55 +1,124 @@
-use crate::{common::*, dbus_helpers::*, DBusEntry};
-use dbus::arg::RefArg;
-use std::iter::FromIterator;
+use crate::{
+    common::*,
+    dbus_helpers::*,
+    version::{compare_versions, format_version},
+    DBusDict, Error, Release, Status,
+};
+use std::{cmp::Ordering, fmt};
 
 bitflags! {
     /// Describes attributes of a device.
+    #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
     pub struct DeviceFlags: u64 {
         /// Device cannot be removed easily
-        const INTERNAL               = 1;
+        const INTERNAL                   = 1;
         /// Device is updatable in this or any other mode
-        const UPDATABLE              = 1 << 1;
+        const UPDATABLE                  = 1 << 1;
         /// Update can only be done from offline mode
-        const ONLY_OFFLINE           = 1 << 2;
+        const ONLY_OFFLINE               = 1 << 2;
         /// Requires AC power
-        const REQUIRE_AC             = 1 << 3;
+        const REQUIRE_AC                 = 1 << 3;
         /// Is locked and can be unlocked
-        const LOCKED                 = 1 << 4;
+        const LOCKED                     = 1 << 4;
         /// Is found in current metadata
-        const SUPPORTED              = 1 << 5;
+        const SUPPORTED                  = 1 << 5;
         /// Requires a bootloader mode to be manually enabled by the user
-        const NEEDS_BOOTLOADER       = 1 << 6;
+        const NEEDS_BOOTLOADER           = 1 << 6;
         /// Has been registered with other plugins
-        const REGISTERED             = 1 << 7;
+        const REGISTERED                 = 1 << 7;
         /// Requires a reboot to apply firmware or to reload hardware
-        const NEEDS_REBOOT           = 1 << 8;
+        const NEEDS_REBOOT               = 1 << 8;
         /// Has been reported to a metadata server
-        const REPORTED               = 1 << 9;
+        const REPORTED                   = 1 << 9;
         /// User has been notified
-        const NOTIFIED               = 1 << 10;
+        const NOTIFIED                   = 1 << 10;
         /// Always use the runtime version rather than the bootloader
-        const USE_RUNTIME_VERSION    = 1 << 11;
+        const USE_RUNTIME_VERSION        = 1 << 11;
         /// Install composite firmware on the parent before the child
-        const INSTALL_PARENT_FIRST   = 1 << 12;
+        const INSTALL_PARENT_FIRST       = 1 << 12;
         /// Is currently in bootloader mode
-        const IS_BOOTLOADER          = 1 << 13;
+        const IS_BOOTLOADER              = 1 << 13;
         /// The hardware is waiting to be replugged
-        const WAIT_FOR_REPLUG        = 1 << 14;
+        const WAIT_FOR_REPLUG            = 1 << 14;
         /// Ignore validation safety checks when flashing this device
-        const IGNORE_VALIDATION      = 1 << 15;
+        const IGNORE_VALIDATION          = 1 << 15;
         /// Extra metadata can be exposed about this device
-        const TRUSTED                = 1 << 16;
+        const TRUSTED                    = 1 << 16;
         /// Requires system shutdown to apply firmware
-        const NEEDS_SHUTDOWN         = 1 << 17;
+        const NEEDS_SHUTDOWN             = 1 << 17;
         /// Requires the update to be retried with a new plugin
-        const ANOTHER_WRITE_REQUIRED = 1 << 18;
+        const ANOTHER_WRITE_REQUIRED     = 1 << 18;
         /// Do not add instance IDs from the device baseclass
-        const NO_AUTO_INSTANCE_IDS   = 1 << 19;
+        const NO_AUTO_INSTANCE_IDS       = 1 << 19;
         /// Device update needs to be separately activated
-        const NEEDS_ACTIVATION       = 1 << 20;
+        const NEEDS_ACTIVATION           = 1 << 20;
         /// Ensure the version is a valid semantic version, e.g. numbers separated with dots
-        const ENSURE_SEMVER          = 1 << 21;
-        const UNKNOWN                = std::u64::MAX;
+        const ENSURE_SEMVER              = 1 << 21;
+        /// Is in the history database
+        const HISTORICAL                 = 1 << 22;
+        /// Is only updatable when supported by the metadata
+        const ONLY_SUPPORTED             = 1 << 23;
+        /// Is expected to disappear during the update
+        const WILL_DISAPPEAR             = 1 << 24;
+        /// Can have its firmware checksum read back
+        const CAN_VERIFY                 = 1 << 25;
+        /// Can have its whole firmware image read back
+        const CAN_VERIFY_IMAGE           = 1 << 26;
+        /// Has two firmware banks, so a failed update can be recovered
+        const DUAL_IMAGE                 = 1 << 27;
+        /// Recovers to the previous firmware if the update fails
+        const SELF_RECOVERY              = 1 << 28;
+        /// Remains usable while the update is being installed
+        const USABLE_DURING_UPDATE       = 1 << 29;
+        /// Requires the metadata to specify the version being replaced
+        const VERSION_CHECK_REQUIRED     = 1 << 30;
+        /// Installs each release in turn, rather than only the latest
+        const INSTALL_ALL_RELEASES       = 1 << 31;
+        /// The name was set from the metadata
+        const MD_SET_NAME                = 1 << 32;
+        /// The name category was set from the metadata
+        const MD_SET_NAME_CATEGORY       = 1 << 33;
+        /// The version format was set from the metadata
+        const MD_SET_VERFMT              = 1 << 34;
+        /// Adds the GUIDs of the bootloader or runtime counterpart
+        const ADD_COUNTERPART_GUIDS      = 1 << 35;
+        /// Is not matched to other devices by GUID
+        const NO_GUID_MATCHING           = 1 << 36;
+        /// Is updatable, but only with the metadata of a specific vendor
+        const UPDATABLE_HIDDEN           = 1 << 37;
+        /// Does not restart after an update
+        const SKIPS_RESTART              = 1 << 38;
+        /// Has firmware available on more than one branch
+        const HAS_MULTIPLE_BRANCHES      = 1 << 39;
+        /// The firmware is backed up before installing the update
+        const BACKUP_BEFORE_INSTALL      = 1 << 40;
+        /// The icon was set from the metadata
+        const MD_SET_ICON                = 1 << 41;
+        /// All devices with the same GUID are updated at the same time
+        const WILDCARD_INSTALL           = 1 << 42;
+        /// Only upgrades to a newer version are allowed
+        const ONLY_VERSION_UPGRADE       = 1 << 43;
+        /// Is not currently reachable, e.g. a wireless device which is out of range
+        const UNREACHABLE                = 1 << 44;
+        /// Updating may affect full disk encryption
+        const AFFECTS_FDE                = 1 << 45;
+        /// Is no longer supported by its vendor
+        const END_OF_LIFE                = 1 << 46;
+        /// Verifies the signature of the firmware payload
+        const SIGNED_PAYLOAD             = 1 << 47;
+        /// Does not verify the signature of the firmware payload
+        const UNSIGNED_PAYLOAD           = 1 << 48;
+        /// Is emulated from recorded data
+        const EMULATED                   = 1 << 49;
+        /// Is being recorded for emulation
+        const EMULATION_TAG              = 1 << 50;
+        /// Is only updated when the user explicitly requests it
+        const ONLY_EXPLICIT_UPDATES      = 1 << 51;
+        /// Can be recorded for emulation
+        const CAN_EMULATION_TAG          = 1 << 52;
+        /// The version is not checked after installing the update
+        const INSTALL_SKIP_VERSION_CHECK = 1 << 53;
+        const UNKNOWN                    = std::u64::MAX;
     }
 }
 
@@ -57,11 +126,90 @@ impl Default for DeviceFlags {
     fn default() -> Self { DeviceFlags::empty() }
 }
 
+impl fmt::Display for DeviceFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { fmt_flags(self, f) }
+}
+
+bitflags! {
+    /// Describes the problems which prevent a device from being updated.
+    #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+    pub struct DeviceProblems: u64 {
+        /// The battery of the system is too low
+        const SYSTEM_POWER_TOO_LOW = 1;
+        /// The device is unreachable, or out of wireless range
+        const UNREACHABLE          = 1 << 1;
+        /// The battery of the device is too low
+        const POWER_TOO_LOW        = 1 << 2;
+        /// The device is waiting for an update to be applied
+        const UPDATE_PENDING       = 1 << 3;
+        /// The device requires AC power to be connected
+        const REQUIRE_AC_POWER     = 1 << 4;
+        /// The lid of the system is closed
+        const LID_IS_CLOSED        = 1 << 5;
+        /// The device is emulated
+        const IS_EMULATED          = 1 << 6;
+        /// The device does not have a license to be updated
+        const MISSING_LICENSE      = 1 << 7;
+        /// Updates are inhibited by the system
+        const SYSTEM_INHIBIT       = 1 << 8;
+        /// An update of the device is already in progress
+        const UPDATE_IN_PROGRESS   = 1 << 9;
+        /// The device is in use
+        const IN_USE               = 1 << 10;
+        /// The device requires a display to be connected
+        const DISPLAY_REQUIRED     = 1 << 11;
+        /// An equivalent device with a higher priority is updated instead
+        const LOWER_PRIORITY       = 1 << 12;
+    }
+}
+
+impl Default for DeviceProblems {
+    fn default() -> Self { DeviceProblems::empty() }
+}
+
+impl fmt::Display for DeviceProblems {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { fmt_flags(self, f) }
+}
+
+impl DeviceProblems {
+    /// Explains each of the problems in a sentence which may be shown to users.
+    ///
+    /// Problems which are too new for this crate are not explained.
+    pub fn explanations(self) -> impl Iterator<Item = &'static str> {
+        const EXPLANATIONS: &[(DeviceProblems, &str)] = &[
+            (DeviceProblems::SYSTEM_POWER_TOO_LOW, "System power is too low to perform the update"),
+            (DeviceProblems::UNREACHABLE, "Device is unreachable, or out of wireless range"),
+            (DeviceProblems::POWER_TOO_LOW, "Device battery power is too low"),
+            (DeviceProblems::UPDATE_PENDING, "Device is waiting for the update to be applied"),
+            (DeviceProblems::REQUIRE_AC_POWER, "Device requires AC power to be connected"),
+            (DeviceProblems::LID_IS_CLOSED, "Device cannot be used while the lid is closed"),
+            (DeviceProblems::IS_EMULATED, "Device is emulated"),
+            (
+                DeviceProblems::MISSING_LICENSE,
+                "Device does not have the necessary license installed",
+            ),
+            (
+                DeviceProblems::SYSTEM_INHIBIT,
+                "All devices are prevented from update by system inhibit",
+            ),
+            (DeviceProblems::UPDATE_IN_PROGRESS, "An update is in progress"),
+            (DeviceProblems::IN_USE, "Device is in use"),
+            (DeviceProblems::DISPLAY_REQUIRED, "Device requires a display to be plugged in"),
+            (DeviceProblems::LOWER_PRIORITY, "Device is lower priority than an equivalent device"),
+        ];
+
+        EXPLANATIONS
+            .iter()
+            .filter(move |(problem, _)| self.contains(*problem))
+            .map(|&(_, explanation)| explanation)
+    }
+}
+
 /// Describes the state of the last update on a device.
 #[derive(Clone, Copy, Debug, PartialEq)]
-#[repr(u8)]
 pub enum UpdateState {
-    Unknown,
+    /// A state which is unknown, or too new for this crate, with its raw value.
+    Unknown(u32),
     Pending,
     Success,
     Failed,
@@ -69,24 +217,41 @@ pub enum UpdateState {
     FailedTransient,
 }
 
-impl From<u8> for UpdateState {
-    fn from(value: u8) -> Self {
+impl From<u32> for UpdateState {
+    fn from(value: u32) -> Self {
         use self::UpdateState::*;
         match value {
-            0 => Unknown,
             1 => Pending,
             2 => Success,
             3 => Failed,
             4 => NeedsReboot,
             5 => FailedTransient,
-            _ => Unknown,
+            _ => Unknown(value),
         }
     }
 }
+
+impl fmt::Display for UpdateState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::UpdateState::*;
+        let name = match *self {
+            Unknown(value) => return fmt_unknown(value, f),
+            Pending => "pending",
+            Success => "success",
+            Failed => "failed",
+            NeedsReboot => "needs-reboot",
+            FailedTransient => "failed-transient",
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// Describes how the raw version numbers of a device are formatted.
 #[derive(Clone, Copy, Debug, PartialEq)]
-#[repr(u8)]
 pub enum VersionFormat {
-    Unknown,
+    /// A format which is unknown, or too new for this crate, with its raw value.
+    Unknown(u32),
     Plain,
     Number,
     Pair,
@@ -95,13 +260,21 @@ pub enum VersionFormat {
     Bcd,
     IntelMe,
     IntelMe2,
+    SurfaceLegacy,
+    Surface,
+    DellBios,
+    Hex,
+    DellBiosMsb,
+}
+
+impl Default for VersionFormat {
+    fn default() -> Self { VersionFormat::Unknown(0) }
 }
 
-impl From<u8> for VersionFormat {
-    fn from(value: u8) -> Self {
+impl From<u32> for VersionFormat {
+    fn from(value: u32) -> Self {
         use self::VersionFormat::*;
         match value {
-            0 => Unknown,
             1 => Plain,
             2 => Number,
             3 => Pair,
@@ -110,46 +283,98 @@ impl From<u8> for VersionFormat {
             6 => Bcd,
             7 => IntelMe,
             8 => IntelMe2,
-            _ => Unknown,
+            9 => SurfaceLegacy,
+            10 => Surface,
+            11 => DellBios,
+            12 => Hex,
+            13 => DellBiosMsb,
+            _ => Unknown(value),
         }
     }
 }
 
+impl fmt::Display for VersionFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::VersionFormat::*;
+        let name = match *self {
+            Unknown(value) => return fmt_unknown(value, f),
+            Plain => "plain",
+            Number => "number",
+            Pair => "pair",
+            Triplet => "triplet",
+            Quad => "quad",
+            Bcd => "bcd",
+            IntelMe => "intel-me",
+            IntelMe2 => "intel-me2",
+            SurfaceLegacy => "surface-legacy",
+            Surface => "surface",
+            DellBios => "dell-bios",
+            Hex => "hex",
+            DellBiosMsb => "dell-bios-msb",
+        };
+
+        f.write_str(name)
+    }
+}
+
 /// The remote ID of a device.
 #[derive(Clone, Debug, Default, Eq, Hash, PartialEq, Shrinkwrap)]
-pub struct DeviceId(Box<str>);
+pub struct DeviceId(pub(crate) Box<str>);
 
 /// A device that is potentially-supported by fwupd.
 #[derive(Clone, Debug, Default)]
 pub struct Device {
-    pub checksum:           Option<Box<str>>,
-    pub created:            u64,
-    pub description:        Option<Box<str>>,
-    pub device_id:          DeviceId,
-    pub flags:              DeviceFlags,
-    pub flashes_left:       Option<u32>,
-    pub guid:               Box<[Box<str>]>,
-    pub icon:               Box<[Box<str>]>,
-    pub install_duration:   Option<u32>,
-    pub instance_ids:       Box<[Box<str>]>,
-    pub modified:           Option<u64>,
-    pub name:               Box<str>,
-    pub parent_device_id:   Option<DeviceId>,
-    pub plugin:             Box<str>,
-    pub serial:             Option<Box<str>>,
-    pub summary:            Option<Box<str>>,
-    pub update_error:       Option<Box<str>>,
-    pub update_message:     Option<Box<str>>,
-    pub update_state:       Option<UpdateState>,
-    pub vendor_id:          Box<str>,
-    pub vendor:             Box<str>,
-    pub version_bootloader: Option<Box<str>>,
-    pub version_format:     Option<VersionFormat>,
-    pub version_lowest:     Option<Box<str>>,
-    pub version:            Box<str>,
+    /// The branch of the current firmware, or `None` for the default branch.
+    pub branch:                 Option<Box<str>>,
+    pub checksum:               Option<Box<str>>,
+    pub created:                u64,
+    pub description:            Option<Box<str>>,
+    pub device_id:              DeviceId,
+    pub flags:                  DeviceFlags,
+    pub flashes_left:           Option<u32>,
+    pub guid:                   Box<[Box<str>]>,
+    pub icon:                   Box<[Box<str>]>,
+    pub install_duration:       Option<u32>,
+    pub instance_ids:           Box<[Box<str>]>,
+    pub modified:               Option<u64>,
+    pub name:                   Box<str>,
+    pub parent_device_id:       Option<DeviceId>,
+    /// The progress of the current action of the device, reported by newer daemons.
+    pub percentage:             Option<u8>,
+    pub plugin:                 Box<str>,
+    /// The problems which currently prevent the device from being updated.
+    pub problems:               DeviceProblems,
+    pub serial:                 Option<Box<str>>,
+    /// The current action of the device, reported by newer daemons.
+    pub status:                 Option<Status>,
+    pub summary:                Option<Box<str>>,
+    pub update_error:           Option<Box<str>>,
+    pub update_message:         Option<Box<str>>,
+    pub update_state:           Option<UpdateState>,
+    pub vendor_id:              Box<str>,
+    pub vendor:                 Box<str>,
+    pub version_bootloader:     Option<Box<str>>,
+    pub version_bootloader_raw: Option<u64>,
+    pub version_format:         Option<VersionFormat>,
+    pub version_lowest:         Option<Box<str>>,
+    pub version_lowest_raw:     Option<u64>,
+    pub version_raw:            Option<u64>,
+    pub version:                Box<str>,
 }
 
 impl Device {
+    /// Compares the version of the device to the version of a release, according to the
+    /// version format of the device.
+    pub fn compare_version(&self, release: &Release) -> Ordering {
+        let format = self.version_format.unwrap_or_default();
+        compare_versions(&self.version, &release.version, format)
+    }
+
+    /// Formats a raw version of the device according to its version format.
+    pub fn format_version(&self, raw: u64) -> String {
+        format_version(raw, self.version_format.unwrap_or_default())
+    }
+
     /// Check if the given `DeviceFlag` is set.
     pub fn has_flag(&self, flags: DeviceFlags) -> bool { self.flags.contains(flags) }
 
@@ -167,87 +392,91 @@ impl Device {
 
     /// Check if the device must be updated offline.
     pub fn only_offline(&self) -> bool { self.has_flag(DeviceFlags::ONLY_OFFLINE) }
+
+    /// Check if any problems currently prevent the device from being updated.
+    pub fn has_problems(&self) -> bool { !self.problems.is_empty() }
+}
+
+impl PartialEq<Release> for Device {
+    fn eq(&self, release: &Release) -> bool { self.compare_version(release) == Ordering::Equal }
+}
+
+impl PartialOrd<Release> for Device {
+    fn partial_cmp(&self, release: &Release) -> Option<Ordering> {
+        Some(self.compare_version(release))
+    }
 }
 
 impl AsRef<DeviceId> for Device {
     fn as_ref(&self) -> &DeviceId { &self.device_id }
 }
 
-impl FromIterator<DBusEntry> for Device {
-    fn from_iter<T>(iter: T) -> Self
-    where
-        T: IntoIterator<Item = DBusEntry>,
-    {
+impl TryFrom<DBusDict> for Device {
+    type Error = Error;
+
+    fn try_from(dict: DBusDict) -> Result<Self, Error> {
         let mut device = Device::default();
 
-        for (key, value) in iter {
+        for (key, value) in dict {
             let key = key.as_str();
             match key {
-                KEY_CHECKSUM => device.checksum = Some(dbus_str(&value, key).into()),
-                KEY_CREATED => device.created = dbus_u64(&value, key),
-                KEY_DESCRIPTION => device.description = Some(dbus_str(&value, key).into()),
-                KEY_DEVICE_ID => device.device_id = DeviceId(dbus_str(&value, key).into()),
-                KEY_FLAGS => device.flags = DeviceFlags::from_bits_truncate(dbus_u64(&value, key)),
-                KEY_FLASHES_LEFT => device.flashes_left = Some(dbus_u64(&value, key) as u32),
-                KEY_GUID => {
-                    device.guid = value
-                        .as_iter()
-                        .expect("Guid is not a variant")
-                        .flat_map(|array| array.as_iter().expect("Guid is not an iterator"))
-                        .map(|elem| dbus_str(elem, key).into())
-                        .collect::<Vec<Box<str>>>()
-                        .into_boxed_slice()
-                }
-                KEY_ICON => {
-                    device.icon = value
-                        .as_iter()
-                        .expect("Icon is not a variant")
-                        .flat_map(|array| array.as_iter().expect("Icon is not an iterator"))
-                        .map(|elem| dbus_str(elem, key).into())
-                        .collect::<Vec<Box<str>>>()
-                        .into_boxed_slice()
-                }
+                KEY_BRANCH => device.branch = Some(dbus_str(&value, key)?.into()),
+                KEY_CHECKSUM => device.checksum = Some(dbus_str(&value, key)?.into()),
+                KEY_CREATED => device.created = dbus_u64(&value, key)?,
+                KEY_DESCRIPTION => device.description = Some(dbus_str(&value, key)?.into()),
+                KEY_DEVICE_ID => device.device_id = DeviceId(dbus_str(&value, key)?.into()),
+                KEY_FLAGS => device.flags = DeviceFlags::from_bits_retain(dbus_u64(&value, key)?),
+                KEY_FLASHES_LEFT => device.flashes_left = Some(dbus_u64(&value, key)? as u32),
+                KEY_GUID => device.guid = dbus_str_array(&value, key)?,
+                KEY_ICON => device.icon = dbus_str_array(&value, key)?,
                 KEY_INSTALL_DURATION => {
-                    device.install_duration = Some(dbus_u64(&value, key) as u32)
-                }
-                KEY_INSTANCE_IDS => {
-                    device.instance_ids = value
-                        .as_iter()
-                        .expect("InstanceIds is not a variant")
-                        .flat_map(|array| array.as_iter().expect("InstanceIds is not an iterator"))
-                        .map(|value| dbus_str(value, key).into())
-                        .collect::<Vec<Box<str>>>()
-                        .into_boxed_slice()
+                    device.install_duration = Some(dbus_u64(&value, key)? as u32)
                 }
-                KEY_MODIFIED => device.modified = Some(dbus_u64(&value, key)),
-                KEY_NAME => device.name = dbus_str(&value, key).into(),
+                KEY_INSTANCE_IDS => device.instance_ids = dbus_str_array(&value, key)?,
+                KEY_MODIFIED => device.modified = Some(dbus_u64(&value, key)?),
+                KEY_NAME => device.name = dbus_str(&value, key)?.into(),
                 KEY_PARENT_DEVICE_ID => {
-                    device.parent_device_id = Some(DeviceId(dbus_str(&value, key).into()))
+                    device.parent_device_id = Some(DeviceId(dbus_str(&value, key)?.into()))
                 }
-                KEY_PLUGIN => device.plugin = dbus_str(&value, key).into(),
-                KEY_SERIAL => device.serial = Some(dbus_str(&value, key).into()),
-                KEY_SUMMARY => device.summary = Some(dbus_str(&value, key).into()),
-                KEY_UPDATE_ERROR => device.update_error = Some(dbus_str(&value, key).into()),
-                KEY_UPDATE_MESSAGE => device.update_message = Some(dbus_str(&value, key).into()),
+                KEY_PERCENTAGE => device.percentage = Some(dbus_u64(&value, key)? as u8),
+                KEY_PLUGIN => device.plugin = dbus_str(&value, key)?.into(),
+                KEY_PROBLEMS => {
+                    device.problems = DeviceProblems::from_bits_retain(dbus_u64(&value, key)?)
+                }
+                KEY_SERIAL => device.serial = Some(dbus_str(&value, key)?.into()),
+                KEY_STATUS => device.status = Some(Status::from(dbus_u64(&value, key)? as u32)),
+                KEY_SUMMARY => device.summary = Some(dbus_str(&value, key)?.into()),
+                KEY_UPDATE_ERROR => device.update_error = Some(dbus_str(&value, key)?.into()),
+                KEY_UPDATE_MESSAGE => device.update_message = Some(dbus_str(&value, key)?.into()),
                 KEY_UPDATE_STATE => {
-                    device.update_state = Some(UpdateState::from(dbus_u64(&value, key) as u8))
+                    device.update_state = Some(UpdateState::from(dbus_u64(&value, key)? as u32))
                 }
-                KEY_VENDOR => device.vendor = dbus_str(&value, key).into(),
-                KEY_VENDOR_ID => device.vendor_id = dbus_str(&value, key).into(),
-                KEY_VERSION => device.version = dbus_str(&value, key).into(),
+                KEY_VENDOR => device.vendor = dbus_str(&value, key)?.into(),
+                KEY_VENDOR_ID => device.vendor_id = dbus_str(&value, key)?.into(),
+                KEY_VERSION => device.version = dbus_str(&value, key)?.into(),
                 KEY_VERSION_BOOTLOADER => {
-                    device.version_bootloader = Some(dbus_str(&value, key).into())
+                    device.version_bootloader = Some(dbus_str(&value, key)?.into())
+                }
+                KEY_VERSION_BOOTLOADER_RAW => {
+                    device.version_bootloader_raw = Some(dbus_u64(&value, key)?)
                 }
-                KEY_VERSION_LOWEST => device.version_lowest = Some(dbus_str(&value, key).into()),
-                "VersionFormat" => {
-                    device.version_format = Some(VersionFormat::from(dbus_u64(&value, key) as u8))
+                KEY_VERSION_FORMAT => {
+                    device.version_format = Some(VersionFormat::from(dbus_u64(&value, key)? as u32))
                 }
+                KEY_VERSION_LOWEST => device.version_lowest = Some(dbus_str(&value, key)?.into()),
+                KEY_VERSION_LOWEST_RAW => device.version_lowest_raw = Some(dbus_u64(&value, key)?),
+                KEY_VERSION_RAW => device.version_raw = Some(dbus_u64(&value, key)?),
                 other => {
-                    eprintln!("unknown device key: {} ({}): {:?}", other, value.signature(), value,);
+                    eprintln!(
+                        "unknown device key: {} ({}): {:?}",
+                        other,
+                        value.value_signature(),
+                        value
+                    );
                 }
             }
         }
 
-        device
+        Ok(device)
     }
 }
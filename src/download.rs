@@ -0,0 +1,126 @@
+use crate::Cancellable;
+use base64::write::EncoderWriter as Base64Encoder;
+use std::io::{self, Read, Write};
+
+/// An error of a [`Downloader`].
+pub type DownloadError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A request to download a file from a remote.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct DownloadRequest<'a> {
+    /// The URI of the file.
+    pub uri:      &'a str,
+    /// The username for HTTP basic authentication, if the remote requires it.
+    pub username: Option<&'a str>,
+    /// The password for HTTP basic authentication.
+    pub password: Option<&'a str>,
+    /// The offset to start downloading from, when resuming a partial download.
+    pub offset:   u64,
+}
+
+impl<'a> DownloadRequest<'a> {
+    pub fn new(uri: &'a str) -> Self { Self { uri, username: None, password: None, offset: 0 } }
+}
+
+/// The response of a [`Downloader`] to a request.
+#[non_exhaustive]
+pub struct Download {
+    /// The contents of the file, from the offset of the download.
+    pub body:   Box<dyn Read + Send>,
+    /// The offset in the file at which the body starts.
+    ///
+    /// This is zero if the whole file is sent, even if the request had an offset.
+    pub offset: u64,
+}
+
+impl Download {
+    pub fn new(body: Box<dyn Read + Send>) -> Self { Self { body, offset: 0 } }
+
+    /// Marks the body as the remainder of the file from the given offset.
+    pub fn with_offset(mut self, offset: u64) -> Self {
+        self.offset = offset;
+        self
+    }
+}
+
+/// Downloads firmware and metadata from remotes on behalf of a client.
+///
+/// A [`UreqDownloader`] is used by default, and may be replaced with
+/// [`ClientBuilder::downloader`](crate::ClientBuilder::downloader) to use a different HTTP
+/// stack, a local mirror, or an in-memory stub in tests.
+pub trait Downloader: Send + Sync {
+    fn download(&self, request: &DownloadRequest) -> Result<Download, DownloadError>;
+}
+
+/// Downloads files over HTTP with a `ureq::Agent`.
+pub struct UreqDownloader {
+    agent: ureq::Agent,
+}
+
+impl UreqDownloader {
+    pub fn new(agent: ureq::Agent) -> Self { Self { agent } }
+}
+
+impl Downloader for UreqDownloader {
+    fn download(&self, request: &DownloadRequest) -> Result<Download, DownloadError> {
+        let mut http = self.agent.get(request.uri);
+
+        // Set the username and password.
+        if let Some(username) = request.username {
+            // Basic HTTP Auth
+            let mut header_value = b"Basic ".to_vec();
+
+            {
+                let mut encoder = Base64Encoder::new(&mut header_value, base64::STANDARD);
+                write!(encoder, "{}:", username)?;
+                if let Some(password) = request.password {
+                    write!(encoder, "{}", password)?;
+                }
+            }
+
+            if let Ok(value) = String::from_utf8(header_value) {
+                http = http.set("Authorization", &value);
+            }
+        }
+
+        if request.offset != 0 {
+            http = http.set("Range", &format!("bytes={}-", request.offset));
+        }
+
+        let response = http.call().map_err(Box::new)?;
+
+        // The remote may ignore the range, and respond with the whole file instead.
+        let offset = if response.status() == 206 { request.offset } else { 0 };
+
+        Ok(Download::new(Box::new(response.into_reader())).with_offset(offset))
+    }
+}
+
+/// Copies the body of a download to a writer, reporting the bytes written after each chunk.
+///
+/// Returns `Ok(false)` if the copy stopped because it was cancelled.
+pub(crate) fn copy_download<W: Write>(
+    body: &mut dyn Read,
+    writer: &mut W,
+    cancellable: Option<&Cancellable>,
+    mut progress: impl FnMut(usize),
+) -> io::Result<bool> {
+    let mut buffer = vec![0u8; 8192];
+
+    loop {
+        if cancellable.map_or(false, Cancellable::is_cancelled) {
+            return Ok(false);
+        }
+
+        let read = match body.read(&mut buffer[..]) {
+            Ok(0) => return Ok(true),
+            Ok(read) => read,
+            Err(why) if why.kind() == io::ErrorKind::Interrupted => continue,
+            Err(why) => return Err(why),
+        };
+
+        writer.write_all(&buffer[..read])?;
+        progress(read);
+    }
+}
@@ -0,0 +1,121 @@
+use crate::common::validate_checksum;
+use crypto_hash::Algorithm;
+use flate2::read::GzDecoder;
+use hex_view::HexView;
+use serde::Deserialize;
+use std::io::{self, Read, Seek, SeekFrom};
+
+const BLOB_KIND_SHA256: u64 = 1;
+const BLOB_KIND_SHA1: u64 = 4;
+const BLOB_KIND_SHA512: u64 = 10;
+
+/// The data of the blob is stored as a string, rather than in base64.
+const BLOB_FLAG_IS_UTF8: u64 = 1;
+
+/// An error that may occur when verifying metadata against a JCAT file.
+#[derive(Debug, Error)]
+pub enum JcatError {
+    #[error("{} does not match its {} checksum", _0, _1)]
+    Mismatch(Box<str>, &'static str),
+    #[error("failed to read the metadata to verify")]
+    Metadata(#[source] io::Error),
+    #[error("JCAT file does not contain checksums for {}", _0)]
+    NoChecksums(Box<str>),
+    #[error("JCAT file does not contain an item for {}", _0)]
+    NoItem(Box<str>),
+    #[error("JCAT file is not valid JSON")]
+    Json(#[source] serde_json::Error),
+    #[error("JCAT file is malformed: {}", _0)]
+    Parse(&'static str),
+    #[error("failed to read JCAT file")]
+    Read(#[source] io::Error),
+}
+
+/// Verifies a file against the checksums of its item in a JCAT file.
+///
+/// The JCAT file may be compressed with gzip. Signatures in the JCAT file are left for the
+/// daemon to verify, but at least one SHA1, SHA256 or SHA512 checksum must be present.
+pub(crate) fn verify<J: Read, F: Read + Seek>(
+    mut jcat: J,
+    id: &str,
+    file: &mut F,
+) -> Result<(), JcatError> {
+    let mut data = Vec::new();
+    jcat.read_to_end(&mut data).map_err(JcatError::Read)?;
+
+    if data.starts_with(&[0x1f, 0x8b]) {
+        let mut json = Vec::new();
+        GzDecoder::new(data.as_slice()).read_to_end(&mut json).map_err(JcatError::Read)?;
+        data = json;
+    }
+
+    let jcat: Jcat = serde_json::from_slice(&data).map_err(JcatError::Json)?;
+
+    let item = jcat
+        .items
+        .into_iter()
+        .find(|item| item.id == id)
+        .ok_or_else(|| JcatError::NoItem(id.into()))?;
+
+    let mut verified = 0;
+    for blob in item.blobs {
+        let (algorithm, name) = match blob.kind {
+            BLOB_KIND_SHA1 => (Algorithm::SHA1, "SHA1"),
+            BLOB_KIND_SHA256 => (Algorithm::SHA256, "SHA256"),
+            BLOB_KIND_SHA512 => (Algorithm::SHA512, "SHA512"),
+            _ => continue,
+        };
+
+        let checksum = if blob.flags & BLOB_FLAG_IS_UTF8 != 0 {
+            blob.data
+        } else {
+            let bytes =
+                base64::decode(&blob.data).map_err(|_| JcatError::Parse("invalid base64"))?;
+            // The checksum is normally hex, but may also be the digest itself.
+            String::from_utf8(bytes)
+                .unwrap_or_else(|why| format!("{:x}", HexView::from(why.as_bytes())))
+        };
+
+        file.seek(SeekFrom::Start(0)).map_err(JcatError::Metadata)?;
+        validate_checksum(file, checksum.trim(), algorithm).map_err(|why| match why.kind() {
+            io::ErrorKind::Other => JcatError::Mismatch(id.into(), name),
+            _ => JcatError::Metadata(why),
+        })?;
+
+        verified += 1;
+    }
+
+    if verified == 0 {
+        return Err(JcatError::NoChecksums(id.into()));
+    }
+
+    file.seek(SeekFrom::Start(0)).map_err(JcatError::Metadata)?;
+
+    Ok(())
+}
+
+/// The layout of a JCAT file, keeping only what is needed to verify checksums.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Jcat {
+    items: Vec<JcatItem>,
+}
+
+/// The blobs of a file which is described by a JCAT file.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct JcatItem {
+    id:    String,
+    #[serde(default)]
+    blobs: Vec<JcatBlob>,
+}
+
+/// A checksum or signature of an item in a JCAT file.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct JcatBlob {
+    kind:  u64,
+    #[serde(default)]
+    flags: u64,
+    data:  String,
+}
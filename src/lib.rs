@@ -9,52 +9,83 @@ extern crate log;
 #[macro_use]
 extern crate shrinkwraprs;
 
+#[cfg(feature = "async")]
+mod async_client;
+mod bios;
+mod builder;
+mod cancellable;
 mod common;
 mod dbus_helpers;
 mod device;
+mod download;
+mod jcat;
+#[cfg(feature = "mock")]
+pub mod mock;
+mod plugin;
 mod release;
 mod remote;
 pub mod request;
+mod security;
+mod tree;
+mod version;
+
+pub use self::{
+    bios::*,
+    builder::*,
+    cancellable::*,
+    device::*,
+    download::*,
+    jcat::JcatError,
+    plugin::*,
+    release::*,
+    remote::*,
+    security::*,
+    tree::*,
+    version::{compare_versions, format_version},
+};
 
-pub use self::{device::*, release::*, remote::*};
+#[cfg(feature = "async")]
+pub use self::async_client::AsyncClient;
 
-use base64::write::EncoderWriter as Base64Encoder;
-use dbus::{
-    self,
-    arg::{Arg, Array, Dict, Get, OwnedFd, RefArg, Variant},
-    ffidisp::{
-        stdintf::org_freedesktop_dbus::{Peer, Properties},
-        ConnPath, Connection,
-    },
-    Message,
+use futures_util::{
+    future::{self, Either},
+    StreamExt,
 };
 use request::Request;
 use std::{
-    borrow::Cow,
     collections::HashMap,
+    convert::Infallible,
+    fmt,
     fs::{self, File, OpenOptions},
-    io::{self, Read, Seek, SeekFrom, Write},
+    future::Future,
+    io::{self, Seek, SeekFrom},
     iter::FromIterator,
-    os::unix::io::IntoRawFd,
+    os::unix::io::{FromRawFd, IntoRawFd, OwnedFd},
     path::{Path, PathBuf},
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
+    pin::pin,
+    sync::Arc,
+    time::Duration,
+};
+use zbus::{
+    blocking::{
+        fdo::{DBusProxy, PeerProxy},
+        Connection, MessageIterator, Proxy,
     },
+    export::serde::Serialize,
+    zvariant::{DynamicType, Fd, OwnedValue, Value},
+    Message, MessageStream, MessageType,
 };
-use zbus::zvariant::Value;
 
 pub const DBUS_NAME: &str = "org.freedesktop.fwupd";
 pub const DBUS_IFACE: &str = "org.freedesktop.fwupd";
 pub const DBUS_PATH: &str = "/";
 
-const TIMEOUT: i32 = -1;
-
-pub type DynVariant = Variant<Box<dyn RefArg + 'static>>;
-pub type DBusEntry = (String, DynVariant);
+pub type DynVariant = OwnedValue;
+pub type DBusDict = HashMap<String, DynVariant>;
 
 bitflags! {
     /// Controls the behavior of the install method.
+    #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
     pub struct InstallFlags: u16 {
         const OFFLINE             = 1;
         const ALLOW_REINSTALL     = 1 << 1;
@@ -69,8 +100,30 @@ bitflags! {
     }
 }
 
+impl fmt::Display for InstallFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { common::fmt_flags(self, f) }
+}
+
+impl InstallFlags {
+    /// The boolean options of the daemon's `Install` method which these flags enable.
+    fn options(self) -> impl Iterator<Item = &'static str> {
+        const OPTIONS: &[(InstallFlags, &str)] = &[
+            (InstallFlags::OFFLINE, "offline"),
+            (InstallFlags::ALLOW_OLDER, "allow-older"),
+            (InstallFlags::ALLOW_REINSTALL, "allow-reinstall"),
+            (InstallFlags::ALLOW_BRANCH_SWITCH, "allow-branch-switch"),
+            (InstallFlags::FORCE, "force"),
+            (InstallFlags::IGNORE_POWER, "ignore-power"),
+            (InstallFlags::NO_HISTORY, "no-history"),
+        ];
+
+        OPTIONS.iter().filter(move |(flag, _)| self.contains(*flag)).map(|&(_, option)| option)
+    }
+}
+
 bitflags! {
     /// Sets what features are supported by the client
+    #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
     pub struct FeatureFlags: u64 {
         const CAN_REPORT = 1;
         const DETACH_ACTION = 1 << 1;
@@ -79,19 +132,27 @@ bitflags! {
         const REQUESTS = 1 << 4;
         const FDE_WARNING = 1 << 5;
         const COMMUNITY_TEXT = 1 << 6;
+        const SHOW_PROBLEMS = 1 << 7;
+        const ALLOW_AUTHENTICATION = 1 << 8;
+        const REQUESTS_NON_GENERIC = 1 << 9;
     }
 }
 
+impl fmt::Display for FeatureFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { common::fmt_flags(self, f) }
+}
+
 /// Describes the status of the daemon.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
-#[repr(u8)]
 pub enum Status {
-    Unknown,
+    /// A status which is unknown, or too new for this crate, with its raw value.
+    Unknown(u32),
     Idle,
     Loading,
     Decompressing,
     DeviceRestart,
     DeviceWrite,
+    DeviceVerify,
     Scheduling,
     Downloading,
     DeviceRead,
@@ -99,53 +160,180 @@ pub enum Status {
     WaitingForAuth,
     DeviceBusy,
     Shutdown,
+    WaitingForUser,
 }
 
-impl From<u8> for Status {
-    fn from(value: u8) -> Self {
+impl From<u32> for Status {
+    fn from(value: u32) -> Self {
         use self::Status::*;
         match value {
-            0 => Unknown,
             1 => Idle,
             2 => Loading,
             3 => Decompressing,
             4 => DeviceRestart,
             5 => DeviceWrite,
-            6 => Scheduling,
-            7 => Downloading,
-            8 => DeviceRead,
-            9 => DeviceErase,
-            10 => WaitingForAuth,
-            11 => DeviceBusy,
-            12 => Shutdown,
-            _ => {
-                eprintln!("status value {} is out of range", value);
-                Idle
-            }
+            6 => DeviceVerify,
+            7 => Scheduling,
+            8 => Downloading,
+            9 => DeviceRead,
+            10 => DeviceErase,
+            11 => WaitingForAuth,
+            12 => DeviceBusy,
+            13 => Shutdown,
+            14 => WaitingForUser,
+            _ => Unknown(value),
         }
     }
 }
 
-#[derive(Debug)]
+impl From<Status> for u32 {
+    fn from(status: Status) -> Self {
+        use self::Status::*;
+        match status {
+            Unknown(value) => value,
+            Idle => 1,
+            Loading => 2,
+            Decompressing => 3,
+            DeviceRestart => 4,
+            DeviceWrite => 5,
+            DeviceVerify => 6,
+            Scheduling => 7,
+            Downloading => 8,
+            DeviceRead => 9,
+            DeviceErase => 10,
+            WaitingForAuth => 11,
+            DeviceBusy => 12,
+            Shutdown => 13,
+            WaitingForUser => 14,
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Status::*;
+        let name = match *self {
+            Unknown(value) => return common::fmt_unknown(value, f),
+            Idle => "idle",
+            Loading => "loading",
+            Decompressing => "decompressing",
+            DeviceRestart => "device-restart",
+            DeviceWrite => "device-write",
+            DeviceVerify => "device-verify",
+            Scheduling => "scheduling",
+            Downloading => "downloading",
+            DeviceRead => "device-read",
+            DeviceErase => "device-erase",
+            WaitingForAuth => "waiting-for-auth",
+            DeviceBusy => "device-busy",
+            Shutdown => "shutdown",
+            WaitingForUser => "waiting-for-user",
+        };
+
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
 pub enum FlashEvent {
     DownloadInitiate(u64),
     DownloadUpdate(usize),
     DownloadComplete,
     VerifyingChecksum,
+    /// The firmware was downloaded and verified from the mirror at this URI.
+    DownloadedFrom(Box<str>),
     FlashInProgress,
+    /// The daemon is writing the firmware to the device, with the percentage of completion.
+    DeviceWrite(u8),
+    /// The daemon is verifying the firmware which was written to the device.
+    DeviceVerify(u8),
+    /// The daemon is erasing the device.
+    DeviceErase(u8),
+    /// The device is restarting.
+    DeviceRestart(u8),
+    /// The daemon is reading the firmware back from the device.
+    DeviceRead(u8),
+    /// The daemon is waiting for the user to authenticate the install.
+    WaitingForAuth(u8),
+}
+
+/// Tracks the status of an install from the signals of the daemon.
+#[derive(Default)]
+struct InstallProgress {
+    status:     Option<Status>,
+    percentage: u8,
+    last:       Option<(Status, u8)>,
+}
+
+impl InstallProgress {
+    /// Updates the progress from a signal, returning an event if the phase or percentage changed.
+    fn update(&mut self, device_id: &DeviceId, signal: Signal) -> Option<FlashEvent> {
+        match signal {
+            Signal::PropertiesChanged { interface, changed, .. } if &*interface == DBUS_IFACE => {
+                let property =
+                    |key| changed.get(key).and_then(|v| dbus_helpers::dbus_u64(v, key).ok());
+
+                if let Some(status) = property("Status") {
+                    self.status = Some(Status::from(status as u32));
+                }
+
+                if let Some(percentage) = property("Percentage") {
+                    self.percentage = percentage as u8;
+                }
+            }
+            // The device reports its own progress, which is more precise than the daemon's.
+            Signal::DeviceChanged(device) if &device.device_id == device_id => {
+                self.status = device.status.or(self.status);
+                self.percentage = device.percentage.unwrap_or(self.percentage);
+            }
+            _ => return None,
+        }
+
+        let current = (self.status?, self.percentage);
+        if self.last == Some(current) {
+            return None;
+        }
+
+        self.last = Some(current);
+
+        let (status, percentage) = current;
+        let event = match status {
+            Status::DeviceWrite => FlashEvent::DeviceWrite(percentage),
+            Status::DeviceVerify => FlashEvent::DeviceVerify(percentage),
+            Status::DeviceErase => FlashEvent::DeviceErase(percentage),
+            Status::DeviceRestart => FlashEvent::DeviceRestart(percentage),
+            Status::DeviceRead => FlashEvent::DeviceRead(percentage),
+            Status::WaitingForAuth => FlashEvent::WaitingForAuth(percentage),
+            _ => return None,
+        };
+
+        Some(event)
+    }
 }
 
 /// An error that may occur when using the client.
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("failed to add match on client connection")]
-    AddMatch(#[source] dbus::Error),
+    AddMatch(#[source] zbus::Error),
+    #[error("not authorized to call {} method", _0)]
+    AuthFailed(&'static str, #[source] zbus::Error),
     #[error("argument mismatch in {} method", _0)]
-    ArgumentMismatch(&'static str, #[source] dbus::arg::TypeMismatchError),
+    ArgumentMismatch(&'static str, #[source] zbus::Error),
+    #[error("invalid value for BIOS setting {}", _0)]
+    BiosSetting(Box<str>, #[source] BiosSettingError),
+    #[error("device is already on the {} branch", _0)]
+    BranchAlreadySelected(Box<str>),
+    #[error("no releases found on the {} branch", _0)]
+    BranchNotFound(Box<str>),
     #[error("calling {} method failed", _0)]
-    Call(&'static str, #[source] dbus::Error),
+    Call(&'static str, #[source] zbus::Error),
+    #[error("the operation was cancelled")]
+    Cancelled,
     #[error("unable to establish dbus connection")]
-    Connection(#[source] dbus::Error),
+    Connection(#[source] zbus::Error),
+    #[error("expected {} for {}, found {}", expected, key, found)]
+    Decode { key: Box<str>, expected: &'static str, found: String },
     #[error("the remote firmware which was downloaded has an invalid checksum")]
     FirmwareChecksumMismatch,
     #[error("failed to copy firmware file from remote")]
@@ -153,7 +341,7 @@ pub enum Error {
     #[error("failed to create firmware file in user cache")]
     FirmwareCreate(#[source] io::Error),
     #[error("failed to GET firmware file from remote")]
-    FirmwareGet(#[source] ureq::Error),
+    FirmwareGet(#[source] DownloadError),
     #[error("failed to open firmware file")]
     FirmwareOpen(#[source] io::Error),
     #[error("failed to read firmware file")]
@@ -161,38 +349,60 @@ pub enum Error {
     #[error("failed to seek to beginning of firmware file")]
     FirmwareSeek(#[source] io::Error),
     #[error("failed to get property for {}", _0)]
-    GetProperty(&'static str, #[source] dbus::Error),
+    GetProperty(&'static str, #[source] zbus::Error),
+    #[error("{} method is not supported by the daemon", _0)]
+    NotSupported(&'static str, #[source] zbus::Error),
     #[error("unable to ping the dbus daemon")]
-    Ping(#[source] dbus::Error),
-    #[error("failed to create {} method call", _0)]
-    NewMethodCall(&'static str, String),
+    Ping(#[source] zbus::Error),
     #[error("release does not have any checksums to validate firmware with")]
     ReleaseWithoutChecksums,
+    #[error("release location is not a valid URI for its remote: {}", _0)]
+    ReleaseLocation(Box<str>),
+    #[error("release does not have any locations to download firmware from")]
+    ReleaseWithoutLocations,
     #[error("remote not found")]
     RemoteNotFound,
+    #[error("{} timed out", _0)]
+    Timeout(&'static str),
+}
+
+impl From<Infallible> for Error {
+    fn from(never: Infallible) -> Self { match never {} }
 }
 
 /// A DBus client for interacting with the fwupd daemon.
 pub struct Client {
-    connection: Connection,
+    proxy: Proxy<'static>,
 
     pub client_name: String,
 
-    http: ureq::Agent,
+    downloader: Box<dyn Downloader>,
+
+    firmware_cache: Option<PathBuf>,
+
+    timeout:  Option<Duration>,
+    timeouts: HashMap<&'static str, Option<Duration>>,
+
+    verify_metadata: bool,
 }
 
 impl Client {
-    pub fn new() -> Result<Self, Error> {
-        let connection = Connection::new_system().map_err(Error::Connection)?;
+    /// Creates a client for the fwupd daemon on the system bus.
+    pub fn new() -> Result<Self, Error> { ClientBuilder::new().build() }
 
-        let mut client = Self { connection, client_name: String::new(), http: ureq::Agent::new() };
+    /// Configures a client before connecting to the daemon.
+    pub fn builder() -> ClientBuilder { ClientBuilder::new() }
 
-        // Reassign the user agent of our client
-        client.client_name = ["fwupd/", &*client.daemon_version()?].concat();
-
-        client.http = ureq::AgentBuilder::new().user_agent(client.client_name.as_str()).build();
+    /// Creates a client for a fwupd daemon on the bus at the given address.
+    pub fn from_address(address: &str) -> Result<Self, Error> {
+        ClientBuilder::new().address(address).build()
+    }
 
-        Ok(client)
+    /// Creates a client from an existing connection to the bus that fwupd is on.
+    ///
+    /// Method calls and signal subscriptions of the client will share this connection.
+    pub fn from_connection(connection: Connection) -> Result<Self, Error> {
+        ClientBuilder::new().build_with_connection(connection)
     }
 
     /// Activate a firmware update on the device.
@@ -200,6 +410,46 @@ impl Client {
         self.action_method("Activate", id.as_ref().as_ref())
     }
 
+    /// Adds the best checksum of the release to the approved firmware of the daemon.
+    pub fn approve_release(&self, release: &Release) -> Result<(), Error> {
+        let mut checksums = self.approved_firmware()?;
+        if add_release_checksum(&mut checksums, release)? {
+            self.set_approved_firmware(&checksums)?;
+        }
+
+        Ok(())
+    }
+
+    /// Gets the checksums of the firmware that the daemon is allowed to install.
+    pub fn approved_firmware(&self) -> Result<Vec<Box<str>>, Error> {
+        self.checksums_method("GetApprovedFirmware")
+    }
+
+    /// Adds the best checksum of the release to the blocked firmware of the daemon.
+    pub fn block_release(&self, release: &Release) -> Result<(), Error> {
+        let mut checksums = self.blocked_firmware()?;
+        if add_release_checksum(&mut checksums, release)? {
+            self.set_blocked_firmware(&checksums)?;
+        }
+
+        Ok(())
+    }
+
+    /// Gets the BIOS settings of the system.
+    pub fn bios_settings(&self) -> Result<Vec<BiosSetting>, Error> {
+        self.get_method("GetBiosSettings")
+    }
+
+    /// Gets the checksums of the firmware that the daemon refuses to install.
+    pub fn blocked_firmware(&self) -> Result<Vec<Box<str>>, Error> {
+        self.checksums_method("GetBlockedFirmware")
+    }
+
+    /// Gets the releases of a device grouped by their branch, newest first.
+    pub fn branches(&self, device: &Device) -> Result<Branches, Error> {
+        self.releases(device).map(|releases| release::group_by_branch(device, releases))
+    }
+
     /// Clears the results of an offline update.
     pub fn clear_results<D: AsRef<DeviceId>>(&self, id: D) -> Result<(), Error> {
         self.action_method("ClearResults", id.as_ref().as_ref())
@@ -221,6 +471,35 @@ impl Client {
     /// Gets a list of all the devices that are supported.
     pub fn devices(&self) -> Result<Vec<Device>, Error> { self.get_method("GetDevices") }
 
+    /// Gets the devices of the daemon, arranged by their parents.
+    pub fn device_tree(&self) -> Result<DeviceTree, Error> { self.devices().map(DeviceTree::new) }
+
+    /// Fixes the host security attribute with the given AppStream ID.
+    ///
+    /// Fails with [`Error::NotSupported`] if the attribute cannot be fixed, or
+    /// [`Error::AuthFailed`] if the caller is not authorized to change it.
+    pub fn fix_host_security_attr(&self, appstream_id: &str) -> Result<(), Error> {
+        self.action_method("FixHostSecurityAttr", appstream_id)
+    }
+
+    /// Gets the host security attributes of the system.
+    pub fn host_security_attrs(&self) -> Result<Vec<SecurityAttr>, Error> {
+        self.get_method("GetHostSecurityAttrs")
+    }
+
+    /// Gets the most recent changes to the host security attributes, up to `limit` events.
+    pub fn host_security_events(&self, limit: u32) -> Result<Vec<SecurityEvent>, Error> {
+        const METHOD: &str = "GetHostSecurityEvents";
+
+        let message = self.call_method(METHOD, &(limit,))?;
+        dicts_from_message(METHOD, &message)
+    }
+
+    /// The Host Security ID of the system, as calculated by the daemon, e.g. `HSI:2 (v1.8.4)`.
+    pub fn host_security_id(&self) -> Result<Box<str>, Error> {
+        self.get_property::<String>("HostSecurityId").map(Box::from)
+    }
+
     /// Get a list of all the downgrades possible for a specific device.
     pub fn downgrades<D: AsRef<DeviceId>>(&self, device_id: D) -> Result<Vec<Release>, Error> {
         self.get_device_method("GetDowngrades", device_id.as_ref().as_ref())
@@ -229,97 +508,88 @@ impl Client {
     /// Fetches firmware from a remote and caches it for later use.
     ///
     /// Firmware will only be fetched if it has not already been cached, or the cached firmware has
-    /// an invalid checksum.
+    /// an invalid checksum. A download which failed part of the way is kept in the cache, and
+    /// resumed from where it stopped by the next fetch of the same firmware.
+    ///
+    /// Each location of the release is tried in turn, after the `firmware_base_uri` of its
+    /// remote, and the mirror that provided the firmware is reported with
+    /// `FlashEvent::DownloadedFrom`.
+    ///
+    /// If the `cancellable` is cancelled, the download stops and its file is removed.
     pub fn fetch_firmware_from_release<C: FnMut(FlashEvent)>(
         &self,
         device: &Device,
         release: &Release,
+        cancellable: Option<&Cancellable>,
         mut callback: Option<C>,
     ) -> Result<(PathBuf, Option<File>), Error> {
         let remote = self.remote(release)?;
 
         // If remote is local, we already have the firmware.
-        {
-            let filename: Option<Cow<'_, Path>> = match remote.kind {
-                RemoteKind::Local => Some(Cow::Owned(
-                    Path::new(remote.filename_cache.as_ref())
-                        .parent()
-                        .expect("remote filename cache without parent")
-                        .join(Path::new(release.uri.as_ref())),
-                )),
-                RemoteKind::Directory => Some(Cow::Borrowed(Path::new(&release.uri[7..]))),
-                _ => None,
+        if let RemoteKind::Local | RemoteKind::Directory = remote.kind {
+            let location = release.uris().next().ok_or(Error::ReleaseWithoutLocations)?;
+            let filename = if remote.kind == RemoteKind::Local {
+                Path::new(remote.filename_cache.as_ref())
+                    .parent()
+                    .expect("remote filename cache without parent")
+                    .join(Path::new(location))
+            } else {
+                let path = location
+                    .strip_prefix("file://")
+                    .ok_or_else(|| Error::ReleaseLocation(location.into()))?;
+                PathBuf::from(path)
             };
 
-            if let Some(filename) = filename {
-                return Ok((filename.to_path_buf(), None));
-            }
+            return Ok((filename, None));
         }
 
-        // Create URI, substituting if required.
-        let uri = remote.firmware_uri(&release.uri);
-        let file_path = common::cache_path_from_uri(&uri);
-
-        let mut request = self.http.get(uri.to_string().as_str());
-
-        // Set the username and password.
-        if let Some(ref username) = remote.username {
-            let password = remote.password.as_ref();
-
-            // Basic HTTP Auth
-            let mut header_value = b"Basic ".to_vec();
-
-            {
-                let mut encoder = Base64Encoder::new(&mut header_value, base64::STANDARD);
-                write!(encoder, "{}:", username).unwrap();
-                if let Some(password) = password {
-                    write!(encoder, "{}", password).unwrap();
-                }
-            }
-
-            if let Ok(value) = String::from_utf8(header_value) {
-                request = request.set("Authorization", &value);
-            }
-        }
+        // Create the URIs of the mirrors, substituting if required. Every mirror downloads to the
+        // same file, so that a partial download from one mirror may be resumed from another.
+        let uris = remote.firmware_uris(release);
+        let file_path =
+            self.firmware_cache_path(uris.first().ok_or(Error::ReleaseWithoutLocations)?)?;
 
         let (checksum, algorithm) =
             common::find_best_checksum(&release.checksums).ok_or(Error::ReleaseWithoutChecksums)?;
 
         // Closure for downloading the firmware to our file, and then validating that it is correct.
-        let download_and_verify = |mut file: File| {
-            info!("downloading firmware for {} ({})...", device.name, release.version);
+        let mut download_and_verify = |file: &mut File, uri: &str, offset: u64| {
+            info!("downloading firmware for {} ({}) from {}...", device.name, release.version, uri);
             if let Some(ref mut cb) = callback {
                 cb(FlashEvent::DownloadInitiate(release.size));
             }
 
-            let mut response = request.call().map_err(Error::FirmwareGet)?.into_reader();
-
-            match callback {
-                Some(ref mut callback) => {
-                    let result = (|| {
-                        let mut progress = 0;
-                        let mut buffer = vec![0u8; 8192];
+            let request = DownloadRequest {
+                username: remote.username.as_deref(),
+                password: remote.password.as_deref(),
+                offset,
+                ..DownloadRequest::new(uri)
+            };
+            let mut download = self.downloader.download(&request).map_err(Error::FirmwareGet)?;
+
+            // Discard what the remote is sending again, which is everything if it ignored the
+            // offset.
+            file.set_len(download.offset).map_err(Error::FirmwareCopy)?;
+            file.seek(SeekFrom::Start(download.offset)).map_err(Error::FirmwareSeek)?;
+
+            let mut progress = download.offset as usize;
+            let result = download::copy_download(&mut *download.body, file, cancellable, |read| {
+                if let Some(ref mut callback) = callback {
+                    progress += read;
+                    callback(FlashEvent::DownloadUpdate(progress));
+                }
+            });
 
-                        loop {
-                            let read = response.read(&mut buffer[..])?;
-                            if read == 0 {
-                                break;
-                            }
-                            file.write_all(&buffer[..read])?;
-                            progress += read;
-                            callback(FlashEvent::DownloadUpdate(progress))
-                        }
+            if let Ok(false) = result {
+                info!("download of firmware for {} was cancelled", device.name);
+                return Err(Error::Cancelled);
+            }
 
-                        Ok(file)
-                    })();
+            if let Some(ref mut callback) = callback {
+                callback(FlashEvent::DownloadComplete);
+            }
 
-                    callback(FlashEvent::DownloadComplete);
-                    file = result.map_err(Error::FirmwareCopy)?;
-                }
-                None => {
-                    io::copy(&mut response, &mut file).map_err(Error::FirmwareCopy)?;
-                }
-            };
+            result.map_err(Error::FirmwareCopy)?;
 
             file.seek(SeekFrom::Start(0)).map_err(Error::FirmwareSeek)?;
 
@@ -328,13 +598,17 @@ impl Client {
             }
 
             info!("validating firmware for {} ({})", device.name, release.version);
-            let checksum_matched = common::validate_checksum(&mut file, checksum, algorithm);
+            let checksum_matched = common::validate_checksum(file, checksum, algorithm);
 
             if checksum_matched.is_err() {
                 return Err(Error::FirmwareChecksumMismatch);
             }
 
-            Ok(file)
+            if let Some(ref mut cb) = callback {
+                cb(FlashEvent::DownloadedFrom(uri.into()));
+            }
+
+            Ok(())
         };
 
         let mut file = None;
@@ -354,23 +628,57 @@ impl Client {
         };
 
         if firmware_requires_fetching {
-            let download = OpenOptions::new()
+            let mut download = OpenOptions::new()
                 .read(true)
                 .write(true)
                 .create(true)
                 .open(&file_path)
                 .map_err(Error::FirmwareCreate)?;
 
-            // If any error occurs when downloading or verifying, delete the file that we created.
-            let download = match download_and_verify(download) {
-                Ok(download) => download,
+            let mut result = Err(Error::ReleaseWithoutLocations);
+
+            // Try each mirror in order, until one of them provides a valid firmware.
+            for uri in uris.iter().map(url::Url::as_str) {
+                // An invalid file in the cache may be a partial download, which can be resumed.
+                let mut offset = download.metadata().map_or(0, |metadata| metadata.len());
+                if release.size != 0 && offset >= release.size {
+                    offset = 0;
+                }
+
+                result = download_and_verify(&mut download, uri, offset);
+
+                // The partial download may be stale or corrupt, so fetch it again in full from
+                // this mirror before moving on to the next one.
+                let resume_failed =
+                    matches!(result, Err(Error::FirmwareGet(_) | Error::FirmwareChecksumMismatch));
+                if offset != 0 && resume_failed {
+                    info!("unable to resume download of firmware, restarting from the beginning");
+                    download.set_len(0).map_err(Error::FirmwareCopy)?;
+                    result = download_and_verify(&mut download, uri, 0);
+                }
+
+                match result {
+                    Ok(()) | Err(Error::Cancelled) => break,
+                    // The next mirror must not resume from the invalid firmware of this one.
+                    Err(Error::FirmwareChecksumMismatch) => {
+                        download.set_len(0).map_err(Error::FirmwareCopy)?
+                    }
+                    Err(_) => (),
+                }
+
+                info!("failed to download firmware from {}", uri);
+            }
+
+            match result {
+                Ok(()) => file = Some(download),
+                // Keep what was downloaded so far, so that a later fetch may resume it.
+                Err(why @ Error::FirmwareCopy(_)) => return Err(why),
+                // Otherwise delete the file that we created.
                 Err(why) => {
                     let _ = fs::remove_file(&file_path);
                     return Err(why);
                 }
-            };
-
-            file = Some(download);
+            }
         }
 
         if let Some(ref mut file) = file {
@@ -381,11 +689,14 @@ impl Client {
     }
 
     /// Update firmware for a `Device` with the firmware specified in a `Release`.
+    ///
+    /// The update may be cancelled until the firmware is handed to the daemon to install.
     pub fn update_device_with_release<F: FnMut(FlashEvent)>(
         &self,
         device: &Device,
         release: &Release,
         mut flags: InstallFlags,
+        cancellable: Option<&Cancellable>,
         mut callback: Option<F>,
     ) -> Result<(), Error> {
         if device.only_offline() {
@@ -393,14 +704,42 @@ impl Client {
         }
 
         let (filename, file) =
-            self.fetch_firmware_from_release(device, release, callback.as_mut())?;
+            self.fetch_firmware_from_release(device, release, cancellable, callback.as_mut())?;
 
-        if let Some(ref mut cb) = callback {
-            cb(FlashEvent::FlashInProgress);
+        if cancellable.map_or(false, Cancellable::is_cancelled) {
+            return Err(Error::Cancelled);
         }
 
+        let callback = match callback {
+            Some(ref mut callback) => callback,
+            None => {
+                info!("installing firmware for {} ({})", device.name, release.version);
+                return self.install(device, "(user)", &filename, file, flags);
+            }
+        };
+
+        callback(FlashEvent::FlashInProgress);
+
+        // Follow the progress of the install through the signals of the daemon.
+        let rule = signal_rule().map_err(Error::AddMatch)?;
+        let bus = DBusProxy::new(self.proxy.connection()).map_err(Error::AddMatch)?;
+        bus.add_match_rule(rule.clone()).map_err(|why| Error::AddMatch(why.into()))?;
+
+        let mut progress = InstallProgress::default();
+        let watch = |message: &Message| {
+            if let Ok(Some(signal)) = Signal::from_message(message) {
+                if let Some(event) = progress.update(&device.device_id, signal) {
+                    callback(event);
+                }
+            }
+        };
+
         info!("installing firmware for {} ({})", device.name, release.version);
-        self.install(device, "(user)", &filename, file, flags)
+        let result = self.install_watching(device, "(user)", &filename, file, flags, watch);
+
+        let _ = bus.remove_match_rule(rule);
+
+        result
     }
 
     /// Gets a list of all the past firmware updates.
@@ -417,135 +756,33 @@ impl Client {
         handle: Option<H>,
         flags: InstallFlags,
     ) -> Result<(), Error> {
-        const METHOD: &str = "Install";
-
-        let fd = match handle {
-            Some(handle) => handle.into_raw_fd(),
-            None => OpenOptions::new()
-                .read(true)
-                .open(filename)
-                .map_err(Error::FirmwareOpen)?
-                .into_raw_fd(),
-        };
-
-        let filename = filename.as_os_str().to_str().expect("filename is not UTF-8");
-
-        let mut options: HashMap<&str, DynVariant> = cascade! {
-            HashMap::new();
-            ..insert("reason", Variant(Box::new(reason.to_owned()) as Box<dyn RefArg>));
-            ..insert("filename", Variant(Box::new(filename.to_owned()) as Box<dyn RefArg>));
-        };
-
-        fn boolean_variant(value: bool) -> Variant<Box<dyn RefArg>> {
-            Variant(Box::new(value) as Box<dyn RefArg>)
-        }
-
-        if flags.contains(InstallFlags::OFFLINE) {
-            options.insert("offline", boolean_variant(true));
-        }
-
-        if flags.contains(InstallFlags::ALLOW_OLDER) {
-            options.insert("allow-older", boolean_variant(true));
-        }
-
-        if flags.contains(InstallFlags::ALLOW_REINSTALL) {
-            options.insert("allow-reinstall", boolean_variant(true));
-        }
-
-        if flags.contains(InstallFlags::ALLOW_BRANCH_SWITCH) {
-            options.insert("allow-branch-switch", boolean_variant(true));
-        }
-
-        if flags.contains(InstallFlags::FORCE) {
-            options.insert("force", boolean_variant(true));
-        }
-
-        if flags.contains(InstallFlags::IGNORE_POWER) {
-            options.insert("ignore-power", boolean_variant(true));
-        }
-
-        if flags.contains(InstallFlags::NO_HISTORY) {
-            options.insert("no-history", boolean_variant(true));
-        }
-
-        let id: &str = id.as_ref().as_ref();
-        let cb = |m: Message| m.append3(id, unsafe { OwnedFd::new(fd) }, options);
-
-        self.call_method(METHOD, cb)?;
-        Ok(())
+        self.install_watching(id, reason, filename, handle, flags, |_| ())
     }
 
     /// Listens for signals from the DBus daemon.
-    pub fn listen_signals(
-        &self,
-        cancellable: Arc<AtomicBool>,
-    ) -> zbus::Result<impl Iterator<Item = Signal> + '_> {
-        let connection = zbus::blocking::Connection::system()?;
-
-        let proxy = zbus::blocking::Proxy::new(
-            &connection,
-            "org.freedesktop.fwupd",
-            "/",
-            "org.freedesktop.fwupd",
-        )?;
-
-        Ok(proxy
-            .receive_all_signals()?
-            .take_while(move |_| cancellable.load(Ordering::SeqCst))
-            .filter_map(|signal| {
-                let signal: zbus::Result<Signal> = match &*signal.member().unwrap() {
-                    "DeviceRequest" => signal.body().map(|array: HashMap<String, Value>| {
-                        let mut request = request::Request::default();
-                        for (key, value) in array {
-                            match key.as_str() {
-                                "AppstreamId" => {
-                                    if let Value::Str(value) = value {
-                                        request.appstream_id = value.as_str().to_owned();
-                                    }
-                                }
-
-                                "Created" => {
-                                    if let Value::U64(value) = value {
-                                        request.created = value;
-                                    }
-                                }
-
-                                "Plugin" => {
-                                    if let Value::Str(value) = value {
-                                        request.plugin = value.as_str().to_owned();
-                                    }
-                                }
-
-                                "RequestKind" => {
-                                    if let Value::U32(value) = value {
-                                        request.request_kind = value;
-                                    }
-                                }
-
-                                "UpdateMessage" => {
-                                    if let Value::Str(value) = value {
-                                        request.update_message = value.as_str().to_owned();
-                                    }
-                                }
-
-                                _ => {
-                                    warn!("unknown DeviceRequest field: {}", key);
-                                }
-                            }
-                        }
-                        Signal::DeviceRequest(request)
-                    }),
-                    _ => return None,
-                };
-
-                match signal {
-                    Ok(signal) => Some(signal),
-                    Err(why) => {
-                        eprintln!("signal error: {}", why);
-                        None
-                    }
+    ///
+    /// Listening stops at the first signal received after the `cancellable` is cancelled.
+    pub fn listen_signals<'a>(
+        &'a self,
+        cancellable: &'a Cancellable,
+    ) -> Result<impl Iterator<Item = Signal> + 'a, Error> {
+        let rule = signal_rule().map_err(Error::AddMatch)?;
+        let messages = MessageIterator::for_match_rule(rule, self.proxy.connection(), None)
+            .map_err(Error::AddMatch)?;
+
+        Ok(messages.take_while(move |_| !cancellable.is_cancelled()).filter_map(|message| {
+            let signal = message
+                .map_err(Error::Connection)
+                .and_then(|message| Signal::from_message(&message));
+
+            match signal {
+                Ok(signal) => signal,
+                Err(why) => {
+                    eprintln!("signal error: {}", why);
+                    None
                 }
-            }))
+            }
+        }))
     }
 
     /// Modifies a device in some way.
@@ -556,7 +793,7 @@ impl Client {
         value: &str,
     ) -> Result<(), Error> {
         let device_id: &str = device_id.as_ref().as_ref();
-        self.call_method("ModifyDevice", |m| m.append3(device_id, key, value))?;
+        self.call_method("ModifyDevice", &(device_id, key, value))?;
         Ok(())
     }
 
@@ -568,7 +805,7 @@ impl Client {
         value: &str,
     ) -> Result<(), Error> {
         let remote_id: &str = remote_id.as_ref().as_ref();
-        self.call_method("ModifyRemote", |m| m.append3(remote_id, key, value))?;
+        self.call_method("ModifyRemote", &(remote_id, key, value))?;
         Ok(())
     }
 
@@ -577,7 +814,17 @@ impl Client {
         self.get_property::<u32>("Percentage").map(|v| v as u8)
     }
 
-    pub fn ping(&self) -> Result<(), Error> { self.connection_path().ping().map_err(Error::Ping) }
+    pub fn ping(&self) -> Result<(), Error> {
+        PeerProxy::builder(self.proxy.connection())
+            .destination(DBUS_NAME)
+            .and_then(|builder| builder.path(DBUS_PATH))
+            .and_then(|builder| builder.build())
+            .and_then(|peer| peer.ping().map_err(zbus::Error::from))
+            .map_err(Error::Ping)
+    }
+
+    /// Gets the plugins of the daemon, and their state.
+    pub fn plugins(&self) -> Result<Vec<Plugin>, Error> { self.get_method("GetPlugins") }
 
     /// Gets a list of all the releases for a specific device.
     pub fn releases<D: AsRef<DeviceId>>(&self, device_id: D) -> Result<Vec<Release>, Error> {
@@ -598,20 +845,85 @@ impl Client {
     /// Gets the results of an offline update.
     pub fn results<D: AsRef<DeviceId>>(&self, id: D) -> Result<Option<Device>, Error> {
         let id: &str = id.as_ref().as_ref();
-        let message = self.call_method("GetResults", |m| m.append1(id))?;
-        let iter: Option<Dict<String, Variant<Box<dyn RefArg + 'static>>, _>> = message.get1();
-        Ok(iter.map(Device::from_iter))
+        let message = self.call_method("GetResults", &(id,))?;
+        let dict: DBusDict =
+            message.body().map_err(|why| Error::ArgumentMismatch("GetResults", why))?;
+        Device::try_from(dict).map(Some)
+    }
+
+    /// Sets the checksums of the firmware that the daemon is allowed to install.
+    pub fn set_approved_firmware<S: AsRef<str>>(&self, checksums: &[S]) -> Result<(), Error> {
+        let checksums: Vec<&str> = checksums.iter().map(AsRef::as_ref).collect();
+        self.call_method("SetApprovedFirmware", &(checksums,))?;
+        Ok(())
+    }
+
+    /// Changes BIOS settings of the system, which are identified by their ID or name.
+    ///
+    /// Every value is validated against the constraints of its setting before any are sent.
+    pub fn set_bios_settings<K, V>(&self, settings: &HashMap<K, V>) -> Result<(), Error>
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let available = self.bios_settings()?;
+        let settings = validate_bios_settings(&available, settings)?;
+        let settings: HashMap<&str, &str> =
+            settings.iter().map(|(id, value)| (*id, value.as_ref())).collect();
+
+        self.call_method("SetBiosSettings", &(settings,))?;
+        Ok(())
+    }
+
+    /// Sets the checksums of the firmware that the daemon refuses to install.
+    pub fn set_blocked_firmware<S: AsRef<str>>(&self, checksums: &[S]) -> Result<(), Error> {
+        let checksums: Vec<&str> = checksums.iter().map(AsRef::as_ref).collect();
+        self.call_method("SetBlockedFirmware", &(checksums,))?;
+        Ok(())
     }
 
     /// Instructs the daemon about which features this client supports.
     pub fn set_feature_flags(&self, feature_flags: FeatureFlags) -> Result<(), Error> {
-        self.call_method("SetFeatureFlags", |m| m.append1(feature_flags.bits()))?;
+        self.call_method("SetFeatureFlags", &(feature_flags.bits(),))?;
         Ok(())
     }
 
     /// The daemon status, e.g. `Decompressing`.
     pub fn status(&self) -> Result<Status, Error> {
-        self.get_property::<u32>("Status").map(|v| Status::from(v as u8))
+        self.get_property::<u32>("Status").map(Status::from)
+    }
+
+    /// Switches a device to another branch of its firmware, by installing the newest release
+    /// on that branch. The default branch is selected with `None`.
+    ///
+    /// The release may be older than the current firmware of the device, so it is installed with
+    /// `ALLOW_BRANCH_SWITCH`, `ALLOW_OLDER` and `ALLOW_REINSTALL`. Returns the release which was
+    /// installed.
+    pub fn switch_branch<F: FnMut(FlashEvent)>(
+        &self,
+        device: &Device,
+        branch: Option<&str>,
+        cancellable: Option<&Cancellable>,
+        callback: Option<F>,
+    ) -> Result<Release, Error> {
+        let name = || Box::from(branch.unwrap_or(release::DEFAULT_BRANCH));
+
+        if device.branch.as_deref() == branch {
+            return Err(Error::BranchAlreadySelected(name()));
+        }
+
+        let release = self
+            .branches(device)?
+            .remove(&branch.map(Box::from))
+            .and_then(|releases| releases.into_iter().next())
+            .ok_or_else(|| Error::BranchNotFound(name()))?;
+
+        let flags = InstallFlags::ALLOW_BRANCH_SWITCH
+            | InstallFlags::ALLOW_OLDER
+            | InstallFlags::ALLOW_REINSTALL;
+
+        self.update_device_with_release(device, &release, flags, cancellable, callback)?;
+        Ok(release)
     }
 
     /// If the daemon has been tainted with a third party plugin.
@@ -622,6 +934,14 @@ impl Client {
         self.action_method("Unlock", id.as_ref().as_ref())
     }
 
+    /// Undoes a fix of the host security attribute with the given AppStream ID.
+    ///
+    /// Fails with [`Error::NotSupported`] if the fix cannot be undone, or
+    /// [`Error::AuthFailed`] if the caller is not authorized to change it.
+    pub fn undo_host_security_attr(&self, appstream_id: &str) -> Result<(), Error> {
+        self.action_method("UndoHostSecurityAttr", appstream_id)
+    }
+
     /// Adds AppStream resource information from a session client.
     pub fn update_metadata<D: IntoRawFd, S: IntoRawFd, R: AsRef<RemoteId>>(
         &self,
@@ -630,13 +950,10 @@ impl Client {
         signature: S,
     ) -> Result<(), Error> {
         let remote_id: &str = remote_id.as_ref().as_ref();
-        let cb = |m: Message| {
-            m.append3(remote_id, unsafe { OwnedFd::new(data.into_raw_fd()) }, unsafe {
-                OwnedFd::new(signature.into_raw_fd())
-            })
-        };
+        let data = unsafe { OwnedFd::from_raw_fd(data.into_raw_fd()) };
+        let signature = unsafe { OwnedFd::from_raw_fd(signature.into_raw_fd()) };
 
-        self.call_method("UpdateMetadata", cb)?;
+        self.call_method("UpdateMetadata", &(remote_id, Fd::from(&data), Fd::from(&signature)))?;
         Ok(())
     }
 
@@ -657,97 +974,413 @@ impl Client {
     }
 
     fn action_method(&self, method: &'static str, id: &str) -> Result<(), Error> {
-        self.call_method(method, |m| m.append1(id))?;
+        self.call_method(method, &(id,))?;
         Ok(())
     }
 
-    fn get_method<T: FromIterator<DBusEntry>>(
-        &self,
-        method: &'static str,
-    ) -> Result<Vec<T>, Error> {
-        let message = self.call_method(method, |m| m)?;
-        let iter: Array<Dict<String, Variant<Box<dyn RefArg + 'static>>, _>, _> =
-            message.read1().map_err(|why| Error::ArgumentMismatch(method, why))?;
+    fn checksums_method(&self, method: &'static str) -> Result<Vec<Box<str>>, Error> {
+        let message = self.call_method(method, &())?;
+        checksums_from_message(method, &message)
+    }
+
+    fn get_method<T>(&self, method: &'static str) -> Result<Vec<T>, Error>
+    where
+        T: TryFrom<DBusDict>,
+        Error: From<T::Error>,
+    {
+        let message = self.call_method(method, &())?;
+        dicts_from_message(method, &message)
+    }
+
+    fn get_device_method<T, C>(&self, method: &'static str, device_id: &str) -> Result<C, Error>
+    where
+        T: TryFrom<DBusDict>,
+        Error: From<T::Error>,
+        C: FromIterator<T>,
+    {
+        let message = self.call_method(method, &(device_id,))?;
+        dicts_from_message(method, &message)
+    }
 
-        Ok(iter.map(T::from_iter).collect())
+    fn get_handle_method<T, H>(&self, method: &'static str, handle: H) -> Result<Vec<T>, Error>
+    where
+        T: TryFrom<DBusDict>,
+        Error: From<T::Error>,
+        H: IntoRawFd,
+    {
+        let handle = unsafe { OwnedFd::from_raw_fd(handle.into_raw_fd()) };
+        let message = self.call_method(method, &(Fd::from(&handle),))?;
+        dicts_from_message(method, &message)
     }
 
-    fn get_device_method<T: FromIterator<DBusEntry>, C: FromIterator<T>>(
+    /// The file which the firmware at `uri` is downloaded to.
+    fn firmware_cache_path(&self, uri: &url::Url) -> Result<PathBuf, Error> {
+        let file = common::cache_file_from_uri(uri);
+        let directory = match self.firmware_cache {
+            Some(ref directory) => directory,
+            None => return Ok(common::cache_path(&file)),
+        };
+
+        let path = directory.join(file);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(Error::FirmwareCreate)?;
+        }
+
+        Ok(path)
+    }
+
+    fn get_property<T>(&self, property: &'static str) -> Result<T, Error>
+    where
+        T: TryFrom<OwnedValue>,
+        T::Error: Into<zbus::Error>,
+    {
+        let result = match self.timeout {
+            Some(_) => zbus::block_on(with_timeout(
+                self.timeout,
+                self.proxy.inner().get_property::<T>(property),
+            )),
+            None => self.proxy.get_property::<T>(property),
+        };
+
+        result.map_err(|why| property_error(property, why))
+    }
+
+    fn call_method<B: Serialize + DynamicType>(
         &self,
         method: &'static str,
-        device_id: &str,
-    ) -> Result<C, Error> {
-        let message = self.call_method(method, |m| m.append1(device_id))?;
-        let iter: Array<Dict<String, Variant<Box<dyn RefArg + 'static>>, _>, _> =
-            message.read1().map_err(|why| Error::ArgumentMismatch(method, why))?;
+        body: &B,
+    ) -> Result<Arc<Message>, Error> {
+        let result = match self.method_timeout(method) {
+            Some(timeout) => self.send_method_call(method, body, Some(timeout), |_| ()),
+            None => self.proxy.call_method(method, body),
+        };
 
-        Ok(C::from_iter(iter.map(T::from_iter)))
+        result.map_err(|why| method_error(method, why))
     }
 
-    fn get_handle_method<T: FromIterator<DBusEntry>, H: IntoRawFd>(
+    /// Calls a method, passing each message which the connection receives until the reply to
+    /// `watch`.
+    fn call_method_watching<B: Serialize + DynamicType>(
         &self,
         method: &'static str,
-        handle: H,
-    ) -> Result<Vec<T>, Error> {
-        let cb = move |m: Message| m.append1(unsafe { OwnedFd::new(handle.into_raw_fd()) });
+        body: &B,
+        watch: impl FnMut(&Message),
+    ) -> Result<Arc<Message>, Error> {
+        self.send_method_call(method, body, self.method_timeout(method), watch)
+            .map_err(|why| method_error(method, why))
+    }
+
+    /// Installs a firmware, passing the messages received while it installs to `watch`.
+    fn install_watching<D: AsRef<DeviceId>, H: IntoRawFd>(
+        &self,
+        id: D,
+        reason: &str,
+        filename: &Path,
+        handle: Option<H>,
+        flags: InstallFlags,
+        watch: impl FnMut(&Message),
+    ) -> Result<(), Error> {
+        const METHOD: &str = "Install";
+
+        let handle = match handle {
+            Some(handle) => unsafe { OwnedFd::from_raw_fd(handle.into_raw_fd()) },
+            None => {
+                OpenOptions::new().read(true).open(filename).map_err(Error::FirmwareOpen)?.into()
+            }
+        };
 
-        let message = self.call_method(method, cb)?;
-        let iter: Array<Dict<String, Variant<Box<dyn RefArg + 'static>>, _>, _> =
-            message.read1().map_err(|why| Error::ArgumentMismatch(method, why))?;
+        let id: &str = id.as_ref().as_ref();
+        let options = install_options(reason, filename, flags);
 
-        Ok(iter.map(T::from_iter).collect())
+        self.call_method_watching(METHOD, &(id, Fd::from(&handle), options), watch)?;
+        Ok(())
     }
 
-    fn get_property<T: for<'a> Get<'a> + Arg>(&self, property: &'static str) -> Result<T, Error> {
-        self.connection_path()
-            .get::<T>(DBUS_NAME, property)
-            .map_err(|why| Error::GetProperty(property, why))
+    /// The timeout of a method, which may override the default timeout.
+    fn method_timeout(&self, method: &'static str) -> Option<Duration> {
+        self.timeouts.get(method).copied().unwrap_or(self.timeout)
     }
 
-    fn call_method<F: FnOnce(Message) -> Message>(
+    /// Calls a method, waiting for its reply for no longer than `timeout`.
+    ///
+    /// Messages received before the reply are passed to `watch`.
+    fn send_method_call<B: Serialize + DynamicType>(
         &self,
         method: &'static str,
-        append_args: F,
-    ) -> Result<Message, Error> {
-        let mut m = Message::new_method_call(DBUS_NAME, DBUS_PATH, DBUS_IFACE, method)
-            .map_err(|why| Error::NewMethodCall(method, why))?;
+        body: &B,
+        timeout: Option<Duration>,
+        mut watch: impl FnMut(&Message),
+    ) -> zbus::Result<Arc<Message>> {
+        let connection = self.proxy.connection().inner();
+        let message = zbus::MessageBuilder::method_call(DBUS_PATH, method)?
+            .destination(DBUS_NAME)?
+            .interface(DBUS_IFACE)?
+            .build(body)?;
+
+        zbus::block_on(async move {
+            // Subscribe to replies before sending, so that the reply cannot be missed.
+            let mut messages = MessageStream::from(connection);
+            let serial = connection.send_message(message).await?;
+
+            let reply = async move {
+                while let Some(message) = messages.next().await {
+                    let message = message?;
+                    let reply_serial = message.header()?.reply_serial()?;
+                    if reply_serial != Some(serial) {
+                        watch(&message);
+                        continue;
+                    }
+
+                    return match message.message_type() {
+                        MessageType::Error => Err(zbus::Error::from(message)),
+                        _ => Ok(message),
+                    };
+                }
 
-        m = append_args(m);
+                Err(zbus::Error::InvalidReply)
+            };
 
-        self.connection
-            .send_with_reply_and_block(m, TIMEOUT)
-            .map_err(|why| Error::Call(method, why))
+            with_timeout(timeout, reply).await
+        })
     }
+}
 
-    fn connection_path(&self) -> ConnPath<&Connection> {
-        self.connection.with_path(DBUS_NAME, DBUS_PATH, TIMEOUT)
+/// Waits for a call to the daemon for no longer than `timeout`, failing with a timed out error.
+async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    call: impl Future<Output = zbus::Result<T>>,
+) -> zbus::Result<T> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return call.await,
+    };
+
+    match future::select(pin!(call), pin!(sleep(timeout))).await {
+        Either::Left((result, _)) => result,
+        Either::Right(_) => Err(zbus::Error::InputOutput(Arc::new(io::ErrorKind::TimedOut.into()))),
     }
 }
 
-/// Signal received by the daemon when listening for signal events with `Client::listen_signals()`.
-#[derive(Debug)]
-pub enum Signal {
-    /// Some value on the interface or the number of devices or profiles has changed.
-    Changed,
-    /// A device has been added.
-    DeviceAdded(Device),
-    /// A device has been changed.
-    DeviceChanged(Device),
-    /// A device has been removed.
-    DeviceRemoved(Device),
-    /// Request for user interaction
-    DeviceRequest(Request),
-    /// Triggers when a property has changed.
-    PropertiesChanged {
+/// Waits for `duration` on the runtime which drives zbus.
+async fn sleep(duration: Duration) {
+    #[cfg(feature = "tokio")]
+    tokio::time::sleep(duration).await;
+
+    #[cfg(not(feature = "tokio"))]
+    async_io::Timer::after(duration).await;
+}
+
+/// Adds the best checksum of a release to a list of checksums, if it is not already in it.
+///
+/// Returns `true` if the list was changed.
+fn add_release_checksum(checksums: &mut Vec<Box<str>>, release: &Release) -> Result<bool, Error> {
+    let (checksum, _) =
+        common::find_best_checksum(&release.checksums).ok_or(Error::ReleaseWithoutChecksums)?;
+
+    if checksums.iter().any(|existing| existing.as_ref() == checksum) {
+        return Ok(false);
+    }
+
+    checksums.push(checksum.into());
+    Ok(true)
+}
+
+/// Decodes the list of firmware checksums in the reply of a daemon method.
+fn checksums_from_message(method: &'static str, message: &Message) -> Result<Vec<Box<str>>, Error> {
+    let checksums: Vec<String> =
+        message.body().map_err(|why| Error::ArgumentMismatch(method, why))?;
+
+    Ok(checksums.into_iter().map(Box::from).collect())
+}
+
+/// Maps the failure to read a property of the daemon.
+fn property_error(property: &'static str, why: zbus::Error) -> Error {
+    match why {
+        zbus::Error::InputOutput(ref io) if io.kind() == io::ErrorKind::TimedOut => {
+            Error::Timeout(property)
+        }
+        why => Error::GetProperty(property, why),
+    }
+}
+
+/// Maps the failure of a daemon method, distinguishing the errors that callers can act on.
+fn method_error(method: &'static str, why: zbus::Error) -> Error {
+    let name = match &why {
+        zbus::Error::InputOutput(io) if io.kind() == io::ErrorKind::TimedOut => {
+            return Error::Timeout(method)
+        }
+        zbus::Error::MethodError(name, ..) => name.as_str(),
+        _ => "",
+    };
+
+    match name {
+        "org.freedesktop.fwupd.NotSupported" => Error::NotSupported(method, why),
+        "org.freedesktop.fwupd.AuthFailed"
+        | "org.freedesktop.fwupd.AuthExpired"
+        | "org.freedesktop.fwupd.PermissionDenied"
+        | "org.freedesktop.DBus.Error.AccessDenied" => Error::AuthFailed(method, why),
+        _ => Error::Call(method, why),
+    }
+}
+
+/// Builds the options of the daemon's `Install` method.
+fn install_options<'a>(
+    reason: &'a str,
+    filename: &'a Path,
+    flags: InstallFlags,
+) -> HashMap<&'static str, Value<'a>> {
+    let filename = filename.as_os_str().to_str().expect("filename is not UTF-8");
+
+    let mut options: HashMap<&str, Value> = cascade! {
+        HashMap::new();
+        ..insert("reason", Value::from(reason));
+        ..insert("filename", Value::from(filename));
+    };
+
+    for option in flags.options() {
+        options.insert(option, Value::from(true));
+    }
+
+    options
+}
+
+/// Decodes the `aa{sv}` reply of a method into a collection of entities.
+fn dicts_from_message<T, C>(method: &'static str, message: &Message) -> Result<C, Error>
+where
+    T: TryFrom<DBusDict>,
+    Error: From<T::Error>,
+    C: FromIterator<T>,
+{
+    let array: Vec<DBusDict> =
+        message.body().map_err(|why| Error::ArgumentMismatch(method, why))?;
+
+    array.into_iter().map(|dict| T::try_from(dict).map_err(Error::from)).collect()
+}
+
+/// Signal received by the daemon when listening for signal events with `Client::listen_signals()`.
+#[derive(Debug)]
+pub enum Signal {
+    /// Some value on the interface or the number of devices or profiles has changed.
+    Changed,
+    /// A device has been added.
+    DeviceAdded(Device),
+    /// A device has been changed.
+    DeviceChanged(Device),
+    /// A device has been removed.
+    DeviceRemoved(Device),
+    /// Request for user interaction
+    DeviceRequest(Request),
+    /// Triggers when a property has changed.
+    PropertiesChanged {
         interface:   Box<str>,
         changed:     HashMap<String, DynVariant>,
         invalidated: Vec<String>,
     },
 }
 
+impl Signal {
+    /// Decodes a signal emitted by the daemon, returning `None` for signals we do not handle.
+    pub(crate) fn from_message(message: &Message) -> Result<Option<Self>, Error> {
+        let interface = message.interface();
+        let member = message.member();
+
+        let (interface, member) = match (interface.as_deref(), member.as_deref()) {
+            (Some(interface), Some(member)) => (interface, member),
+            _ => return Ok(None),
+        };
+
+        let signal = match (interface, member) {
+            (DBUS_IFACE, "Changed") => Signal::Changed,
+            (DBUS_IFACE, "DeviceAdded") => {
+                Signal::DeviceAdded(device_from_signal("DeviceAdded", message)?)
+            }
+            (DBUS_IFACE, "DeviceChanged") => {
+                Signal::DeviceChanged(device_from_signal("DeviceChanged", message)?)
+            }
+            (DBUS_IFACE, "DeviceRemoved") => {
+                Signal::DeviceRemoved(device_from_signal("DeviceRemoved", message)?)
+            }
+            (DBUS_IFACE, "DeviceRequest") => Signal::DeviceRequest(
+                signal_body("DeviceRequest", message).map(|array: HashMap<String, Value>| {
+                    let mut request = request::Request::default();
+                    for (key, value) in array {
+                        match key.as_str() {
+                            "AppstreamId" => {
+                                if let Value::Str(value) = value {
+                                    request.appstream_id = value.as_str().to_owned();
+                                }
+                            }
+
+                            "Created" => {
+                                if let Value::U64(value) = value {
+                                    request.created = value;
+                                }
+                            }
+
+                            "Plugin" => {
+                                if let Value::Str(value) = value {
+                                    request.plugin = value.as_str().to_owned();
+                                }
+                            }
+
+                            "RequestKind" => {
+                                if let Value::U32(value) = value {
+                                    request.request_kind = value;
+                                }
+                            }
+
+                            "UpdateMessage" => {
+                                if let Value::Str(value) = value {
+                                    request.update_message = value.as_str().to_owned();
+                                }
+                            }
+
+                            _ => {
+                                warn!("unknown DeviceRequest field: {}", key);
+                            }
+                        }
+                    }
+                    request
+                })?,
+            ),
+            ("org.freedesktop.DBus.Properties", "PropertiesChanged") => {
+                let (interface, changed, invalidated): (String, DBusDict, Vec<String>) =
+                    signal_body("PropertiesChanged", message)?;
+
+                Signal::PropertiesChanged { interface: interface.into(), changed, invalidated }
+            }
+            _ => return Ok(None),
+        };
+
+        Ok(Some(signal))
+    }
+}
+
+/// Matches both the signals of the fwupd interface, and the property changes of the daemon.
+fn signal_rule() -> zbus::Result<zbus::MatchRule<'static>> {
+    Ok(zbus::MatchRule::builder()
+        .msg_type(zbus::MessageType::Signal)
+        .sender(DBUS_NAME)?
+        .path(DBUS_PATH)?
+        .build())
+}
+
+fn device_from_signal(signal: &'static str, message: &Message) -> Result<Device, Error> {
+    Device::try_from(signal_body::<DBusDict>(signal, message)?)
+}
+
+fn signal_body<'m, B>(signal: &'static str, message: &'m Message) -> Result<B, Error>
+where
+    B: zbus::export::serde::Deserialize<'m> + zbus::zvariant::Type,
+{
+    message.body().map_err(|why| Error::ArgumentMismatch(signal, why))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(feature = "mock")]
+    use std::io::Read;
 
     fn download_remote() -> Remote {
         Remote {
@@ -770,20 +1403,1376 @@ mod tests {
         }
     }
 
+    #[test]
+    fn device_added_signal() {
+        let body: HashMap<&str, Value> = cascade! {
+            HashMap::new();
+            ..insert("DeviceId", Value::from("1a8d0d9a96ad3e67ba76cf3033623625dc6d6882"));
+            ..insert("Name", Value::from("Thunderbolt Controller"));
+            ..insert("Flags", Value::from(DeviceFlags::UPDATABLE.bits()));
+            ..insert("Guid", Value::from(vec!["7ae5e4bb-3be7-50d4-a5a6-0e5e6ef9b2e3"]));
+        };
+
+        let message = zbus::MessageBuilder::signal(DBUS_PATH, DBUS_IFACE, "DeviceAdded")
+            .unwrap()
+            .build(&body)
+            .unwrap();
+
+        match Signal::from_message(&message).unwrap() {
+            Some(Signal::DeviceAdded(device)) => {
+                assert_eq!(&**device.device_id, "1a8d0d9a96ad3e67ba76cf3033623625dc6d6882");
+                assert_eq!(device.name.as_ref(), "Thunderbolt Controller");
+                assert!(device.is_updateable());
+                assert!(device.has_guid("7ae5e4bb-3be7-50d4-a5a6-0e5e6ef9b2e3"));
+            }
+            other => panic!("expected DeviceAdded signal, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn properties_changed_signal() {
+        let changed: HashMap<&str, Value> = cascade! {
+            HashMap::new();
+            ..insert("Percentage", Value::from(42u32));
+        };
+
+        let message = zbus::MessageBuilder::signal(
+            DBUS_PATH,
+            "org.freedesktop.DBus.Properties",
+            "PropertiesChanged",
+        )
+        .unwrap()
+        .build(&(DBUS_IFACE, changed, Vec::<String>::new()))
+        .unwrap();
+
+        match Signal::from_message(&message).unwrap() {
+            Some(Signal::PropertiesChanged { interface, changed, invalidated }) => {
+                assert_eq!(interface.as_ref(), DBUS_IFACE);
+                assert_eq!(*changed["Percentage"], Value::U32(42));
+                assert!(invalidated.is_empty());
+            }
+            other => panic!("expected PropertiesChanged signal, found {:?}", other),
+        }
+    }
+
+    fn dict(entries: &[(&str, Value)]) -> DBusDict {
+        entries.iter().map(|(key, value)| (key.to_string(), value.to_owned())).collect()
+    }
+
+    fn assert_decode_error<T: std::fmt::Debug>(
+        result: Result<T, Error>,
+        key: &str,
+        expected: &str,
+        found: &str,
+    ) {
+        match result {
+            Err(Error::Decode { key: k, expected: e, found: f }) => {
+                assert_eq!((&*k, e, f.as_str()), (key, expected, found))
+            }
+            other => panic!("expected decode error for {}, found {:?}", key, other),
+        }
+    }
+
+    #[test]
+    fn decode_malformed_device() {
+        let device = Device::try_from(dict(&[
+            ("DeviceId", Value::from("1a8d0d9a96ad3e67ba76cf3033623625dc6d6882")),
+            ("Name", Value::from(42u32)),
+        ]));
+        assert_decode_error(device, "Name", "s", "u");
+
+        let device = Device::try_from(dict(&[("Flags", Value::from("updatable"))]));
+        assert_decode_error(device, "Flags", "t", "s");
+
+        let device = Device::try_from(dict(&[("Guid", Value::from(vec![1u32, 2]))]));
+        assert_decode_error(device, "Guid", "as", "au");
+    }
+
+    #[test]
+    fn decode_malformed_release() {
+        let release = Release::try_from(dict(&[("Checksum", Value::from(vec!["abc"]))]));
+        assert_decode_error(release, "Checksum", "s", "as");
+
+        let release = Release::try_from(dict(&[("Size", Value::from(-1i64))]));
+        assert_decode_error(release, "Size", "t", "x");
+    }
+
+    #[test]
+    fn decode_malformed_remote() {
+        let remote = Remote::try_from(dict(&[("Enabled", Value::from("true"))]));
+        assert_decode_error(remote, "Enabled", "t", "s");
+
+        let remote = Remote::try_from(dict(&[("Priority", Value::from("high"))]));
+        assert_decode_error(remote, "Priority", "x", "s");
+    }
+
+    #[test]
+    fn decode_malformed_reply() {
+        let devices = vec![dict(&[("Name", Value::from(true))])];
+
+        let call = zbus::MessageBuilder::method_call(DBUS_PATH, "GetDevices")
+            .unwrap()
+            .destination(DBUS_NAME)
+            .unwrap()
+            .build(&())
+            .unwrap();
+
+        let reply = zbus::MessageBuilder::method_return(&call.header().unwrap())
+            .unwrap()
+            .build(&(devices,))
+            .unwrap();
+
+        let devices: Result<Vec<Device>, Error> = dicts_from_message("GetDevices", &reply);
+        assert_decode_error(devices, "Name", "s", "b");
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn decode_malformed_results() {
+        let (_bus, daemon, client) = mock_daemon();
+        let device = &client.devices().unwrap()[0];
+
+        daemon.set_results(MOCK_DEVICE_ID, dict(&[("Name", Value::from(true))]));
+        assert_decode_error(client.results(device), "Name", "s", "b");
+
+        daemon.set_results(MOCK_DEVICE_ID, dict(&[("Name", Value::from("Dock"))]));
+        let device = client.results(device).unwrap().unwrap();
+        assert_eq!(&*device.name, "Dock");
+    }
+
+    #[test]
+    fn release_checksum_added_once() {
+        let sha1 = "e1cc4e5d5ee1e8b0dfa1a8ff2dc3cb4d8c25a1c6";
+        let sha256 = "9c5bcd2e9b11e8d4a1d2ac4cd1ee63bbf6d50ab8c1ff3a5ffab3f0f4a6b8e2d1";
+        let release = Release {
+            checksums: vec![Box::from(sha1), Box::from(sha256)].into(),
+            ..Default::default()
+        };
+
+        let mut checksums = Vec::new();
+        assert!(add_release_checksum(&mut checksums, &release).unwrap());
+        assert!(!add_release_checksum(&mut checksums, &release).unwrap());
+        assert_eq!(checksums, vec![Box::from(sha256)]);
+
+        assert!(matches!(
+            add_release_checksum(&mut checksums, &Release::default()),
+            Err(Error::ReleaseWithoutChecksums)
+        ));
+    }
+
+    fn bios_setting(id: &str, kind: BiosSettingKind) -> BiosSetting {
+        BiosSetting {
+            id: id.into(),
+            kind,
+            lower_bound: 1,
+            upper_bound: 100,
+            scalar_increment: 5,
+            possible_values: vec![Box::from("Enabled"), Box::from("Disabled")].into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn bios_setting_validation() {
+        let available = [
+            bios_setting("SecureBoot", BiosSettingKind::Enumeration),
+            bios_setting("FanSpeed", BiosSettingKind::Integer),
+            bios_setting("AssetTag", BiosSettingKind::String),
+            BiosSetting { read_only: true, ..bios_setting("Locked", BiosSettingKind::String) },
+        ];
+
+        let validate = |key: &str, value: &str| {
+            let settings = HashMap::from([(key.to_owned(), value.to_owned())]);
+            validate_bios_settings(&available, &settings)
+                .map(|settings| settings[key].clone().into_owned())
+        };
+
+        let invalid = |key: &str, value: &str| match validate(key, value) {
+            Err(Error::BiosSetting(_, why)) => why,
+            other => panic!("expected invalid BIOS setting, found {:?}", other),
+        };
+
+        assert_eq!(validate("SecureBoot", "enabled").unwrap(), "Enabled");
+        assert_eq!(validate("FanSpeed", "25").unwrap(), "25");
+        assert_eq!(validate("AssetTag", "ABC-123").unwrap(), "ABC-123");
+
+        assert!(matches!(invalid("SecureBoot", "Auto"), BiosSettingError::NotPossible(_)));
+        assert!(matches!(invalid("FanSpeed", "fast"), BiosSettingError::NotInteger(_)));
+        assert!(matches!(invalid("FanSpeed", "105"), BiosSettingError::OutOfBounds(105, 1, 100)));
+        assert!(matches!(invalid("FanSpeed", "27"), BiosSettingError::NotIncrement(27, 5)));
+        assert!(matches!(invalid("AssetTag", ""), BiosSettingError::OutOfBounds(0, 1, 100)));
+        assert!(matches!(invalid("Locked", "value"), BiosSettingError::ReadOnly));
+        assert!(matches!(invalid("Missing", "value"), BiosSettingError::NotFound));
+    }
+
+    fn method_error_named(name: &str) -> Error {
+        let call = zbus::MessageBuilder::method_call(DBUS_PATH, "FixHostSecurityAttr")
+            .unwrap()
+            .destination(DBUS_NAME)
+            .unwrap()
+            .build(&("org.fwupd.hsi.Kernel.Lockdown",))
+            .unwrap();
+
+        let reply = zbus::MessageBuilder::error(&call.header().unwrap(), name)
+            .unwrap()
+            .build(&("failed",))
+            .unwrap();
+
+        method_error("FixHostSecurityAttr", zbus::Error::from(Arc::new(reply)))
+    }
+
+    #[test]
+    fn method_errors() {
+        assert!(matches!(
+            method_error_named("org.freedesktop.fwupd.NotSupported"),
+            Error::NotSupported("FixHostSecurityAttr", _)
+        ));
+        assert!(matches!(
+            method_error_named("org.freedesktop.fwupd.AuthFailed"),
+            Error::AuthFailed("FixHostSecurityAttr", _)
+        ));
+        assert!(matches!(
+            method_error_named("org.freedesktop.fwupd.Internal"),
+            Error::Call("FixHostSecurityAttr", _)
+        ));
+    }
+
+    #[test]
+    fn install_progress_of_device() {
+        let device_id = DeviceId("1a8d0d9a96ad3e67ba76cf3033623625dc6d6882".into());
+        let changed = |device_id: &DeviceId, percentage| {
+            Signal::DeviceChanged(Device {
+                device_id: device_id.clone(),
+                percentage: Some(percentage),
+                status: Some(Status::DeviceWrite),
+                ..Default::default()
+            })
+        };
+
+        let mut progress = InstallProgress::default();
+        assert_eq!(
+            progress.update(&device_id, changed(&device_id, 25)),
+            Some(FlashEvent::DeviceWrite(25))
+        );
+        assert_eq!(progress.update(&device_id, changed(&device_id, 25)), None);
+
+        // The progress of other devices is ignored.
+        let other = DeviceId("other".into());
+        assert_eq!(progress.update(&device_id, changed(&other, 75)), None);
+        assert_eq!(
+            progress.update(&device_id, changed(&device_id, 75)),
+            Some(FlashEvent::DeviceWrite(75))
+        );
+    }
+
+    fn jcat_file(blobs: &str) -> Vec<u8> {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        let json = format!(
+            r#"{{"JcatVersionMajor": 0, "JcatVersionMinor": 1, "Items": [
+                {{"Id": "firmware.xml.gz", "Blobs": [{}]}}
+            ]}}"#,
+            blobs
+        );
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(json.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn jcat_verification() {
+        use crypto_hash::{hex_digest, Algorithm};
+        use std::io::Cursor;
+
+        let metadata = b"<components/>";
+        let sha256 = hex_digest(Algorithm::SHA256, metadata);
+        let sha512 = hex_digest(Algorithm::SHA512, metadata);
+        let verify =
+            |jcat: Vec<u8>, id| jcat::verify(jcat.as_slice(), id, &mut Cursor::new(&metadata[..]));
+
+        let blobs = format!(
+            r#"{{"Kind": 1, "Flags": 1, "Timestamp": 1651000000, "Data": "{}"}},
+               {{"Kind": 10, "Flags": 0, "Data": "{}"}},
+               {{"Kind": 2, "Flags": 1, "Data": "-----BEGIN PGP SIGNATURE-----\n\u00e9"}}"#,
+            sha256,
+            base64::encode(sha512)
+        );
+        assert!(verify(jcat_file(&blobs), "firmware.xml.gz").is_ok());
+        assert!(matches!(verify(jcat_file(&blobs), "firmware.xml.zst"), Err(JcatError::NoItem(_))));
+
+        let corrupt = format!(r#"{{"Kind": 1, "Flags": 1, "Data": "{}"}}"#, "0".repeat(64));
+        assert!(matches!(
+            verify(jcat_file(&corrupt), "firmware.xml.gz"),
+            Err(JcatError::Mismatch(_, "SHA256"))
+        ));
+
+        let unsigned = r#"{"Kind": 2, "Flags": 1, "Data": "signature"}"#;
+        assert!(matches!(
+            verify(jcat_file(unsigned), "firmware.xml.gz"),
+            Err(JcatError::NoChecksums(_))
+        ));
+
+        // Escapes in the JSON are decoded before the item is looked up.
+        let escaped = format!(
+            r#"{{"Items": [{{"Id": "firmware\u002exml.gz", "Blobs": [
+                {{"Kind": 2, "Flags": 1, "Data": "\ud83d\udd12 \"signed\"\t"}},
+                {{"Kind": 1, "Flags": 1, "Data": "{}"}}
+            ]}}]}}"#,
+            sha256
+        );
+        assert!(verify(escaped.into_bytes(), "firmware.xml.gz").is_ok());
+
+        assert!(matches!(
+            verify(b"{\"Items\": [".to_vec(), "firmware.xml.gz"),
+            Err(JcatError::Json(_))
+        ));
+    }
+
+    fn tree_device(id: &str, parent: Option<&str>, flags: DeviceFlags) -> Device {
+        Device {
+            device_id: DeviceId(id.into()),
+            flags,
+            name: id.into(),
+            parent_device_id: parent.map(|parent| DeviceId(parent.into())),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn device_tree() {
+        let none = DeviceFlags::empty();
+        let tree = DeviceTree::new(vec![
+            tree_device("hub", Some("dock"), none),
+            tree_device("dock", None, none),
+            tree_device("pd", Some("dock"), DeviceFlags::INSTALL_PARENT_FIRST),
+            tree_device("hub-port", Some("hub"), none),
+            tree_device("tpm", None, none),
+            tree_device("orphan", Some("unplugged"), none),
+            tree_device("loop-a", Some("loop-b"), none),
+            tree_device("loop-b", Some("loop-a"), none),
+        ]);
+
+        let names = |devices: Vec<&Device>| -> Vec<String> {
+            devices.into_iter().map(|device| device.name.to_string()).collect()
+        };
+        let id = |id: &str| DeviceId(id.into());
+
+        assert_eq!(names(tree.roots().collect()), ["dock", "tpm", "orphan", "loop-a"]);
+        assert_eq!(names(tree.children(&id("dock")).collect()), ["hub", "pd"]);
+        assert_eq!(names(tree.ancestors(&id("hub-port")).collect()), ["hub", "dock"]);
+        assert!(tree.parent(&id("dock")).is_none());
+        assert_eq!(names(tree.composite(&id("hub-port"))), ["dock", "hub", "hub-port", "pd"]);
+        assert_eq!(tree.composites().count(), 4);
+
+        let depths: Vec<usize> = tree.iter().map(|(depth, _)| depth).collect();
+        assert_eq!(depths, [0, 1, 2, 1, 0, 0, 0, 1]);
+
+        let update = [id("dock"), id("pd"), id("hub-port"), id("hub"), id("hub")];
+        assert_eq!(names(tree.install_order(&update)), ["hub-port", "hub", "dock", "pd"]);
+    }
+
+    fn security_attr(hsi_level: u32, flags: SecurityAttrFlags) -> SecurityAttr {
+        SecurityAttr { hsi_level, flags, ..Default::default() }
+    }
+
+    #[test]
+    fn hsi_summary_levels() {
+        let attrs = [
+            security_attr(1, SecurityAttrFlags::SUCCESS),
+            security_attr(1, SecurityAttrFlags::SUCCESS),
+            security_attr(2, SecurityAttrFlags::SUCCESS),
+            security_attr(3, SecurityAttrFlags::empty()),
+            security_attr(4, SecurityAttrFlags::SUCCESS),
+        ];
+        assert_eq!(hsi_summary(&attrs), "HSI:2");
+
+        let attrs = [
+            security_attr(1, SecurityAttrFlags::empty()),
+            security_attr(2, SecurityAttrFlags::SUCCESS),
+        ];
+        assert_eq!(hsi_summary(&attrs), "HSI:0");
+
+        let attrs = [
+            security_attr(1, SecurityAttrFlags::SUCCESS),
+            security_attr(2, SecurityAttrFlags::OBSOLETED),
+            security_attr(0, SecurityAttrFlags::RUNTIME_ISSUE),
+        ];
+        assert_eq!(hsi_summary(&attrs), "HSI:1!");
+    }
+
+    #[test]
+    fn decode_security_attr() {
+        let attr = SecurityAttr::try_from(dict(&[
+            ("AppstreamId", Value::from("org.fwupd.hsi.Uefi.SecureBoot")),
+            ("HsiLevel", Value::from(1u32)),
+            ("HsiResult", Value::from(2u32)),
+            ("Flags", Value::from(SecurityAttrFlags::ACTION_CONFIG_FW.bits())),
+            ("Plugin", Value::from("uefi_capsule")),
+            ("Uri", Value::from("https://fwupd.github.io/hsi.html#org.fwupd.hsi.Uefi.SecureBoot")),
+        ]))
+        .unwrap();
+
+        assert_eq!(&*attr.appstream_id, "org.fwupd.hsi.Uefi.SecureBoot");
+        assert_eq!(attr.hsi_level, 1);
+        assert_eq!(attr.result, HsiResult::NotEnabled);
+        assert!(!attr.is_success());
+        assert!(attr.has_flag(SecurityAttrFlags::ACTION_CONFIG_FW));
+    }
+
+    #[test]
+    fn decode_security_event() {
+        let event = SecurityEvent::try_from(dict(&[
+            ("AppstreamId", Value::from("org.fwupd.hsi.Uefi.SecureBoot")),
+            ("Created", Value::from(1_650_000_000u64)),
+            ("HsiResult", Value::from(2u32)),
+            ("HsiResultFallback", Value::from(1u32)),
+        ]))
+        .unwrap();
+
+        assert_eq!(event.created(), 1_650_000_000);
+        assert_eq!(event.previous(), HsiResult::Enabled);
+        assert_eq!(event.current(), HsiResult::NotEnabled);
+        assert!(event.is_regression());
+    }
+
+    #[test]
+    fn decode_plugin() {
+        let flags = PluginFlags::DISABLED | PluginFlags::LEGACY_BIOS | PluginFlags::USER_WARNING;
+        let plugin = Plugin::try_from(dict(&[
+            ("Name", Value::from("uefi_capsule")),
+            ("Flags", Value::from(flags.bits())),
+        ]))
+        .unwrap();
+
+        assert_eq!(&*plugin.name, "uefi_capsule");
+        assert!(plugin.is_disabled());
+        assert!(plugin.has_flag(PluginFlags::LEGACY_BIOS));
+        assert!(!plugin.has_flag(PluginFlags::ESP_NOT_FOUND));
+    }
+
+    #[test]
+    fn device_problems() {
+        let problems = DeviceProblems::REQUIRE_AC_POWER | DeviceProblems::LID_IS_CLOSED;
+        let device = Device::try_from(dict(&[
+            ("DeviceId", Value::from("dock")),
+            ("Problems", Value::from(problems.bits() | 1 << 40)),
+        ]))
+        .unwrap();
+
+        assert!(device.has_problems());
+        assert!(device.problems.contains(problems));
+        assert_eq!(device.problems.to_string(), "require-ac-power|lid-is-closed|0x10000000000");
+        assert_eq!(
+            device.problems.explanations().collect::<Vec<_>>(),
+            [
+                "Device requires AC power to be connected",
+                "Device cannot be used while the lid is closed",
+            ]
+        );
+
+        let device = Device::try_from(dict(&[("DeviceId", Value::from("dock"))])).unwrap();
+        assert!(!device.has_problems());
+        assert_eq!(device.problems.explanations().count(), 0);
+    }
+
+    #[test]
+    fn version_comparisons() {
+        use std::cmp::Ordering::{self, *};
+
+        const CASES: &[(&str, &str, VersionFormat, Ordering)] = &[
+            ("1.2.3", "1.2.3", VersionFormat::Triplet, Equal),
+            ("1.9", "1.10", VersionFormat::Pair, Less),
+            ("1.10", "1.9", VersionFormat::Pair, Greater),
+            ("001.002", "1.2", VersionFormat::Pair, Equal),
+            ("1.2", "1.2.3", VersionFormat::Unknown(0), Less),
+            ("1.2.3", "1.2", VersionFormat::Unknown(0), Greater),
+            ("1.2.3~rc1", "1.2.3", VersionFormat::Triplet, Less),
+            ("1.2.3a", "1.2.3", VersionFormat::Triplet, Greater),
+            ("1.2.3a", "1.2.3b", VersionFormat::Triplet, Less),
+            ("1.2.3", "0x01020003", VersionFormat::Triplet, Equal),
+            ("0x01020004", "0x01020003", VersionFormat::Triplet, Greater),
+            ("16909060", "1.2.3.4", VersionFormat::Quad, Equal),
+            ("65538", "1.2", VersionFormat::Pair, Equal),
+            ("0x12345678", "12.34.56.78", VersionFormat::Bcd, Equal),
+            ("0x4A0B1D2F", "13.10.11.7471", VersionFormat::IntelMe, Equal),
+            ("0x4A0B1D2F", "4.10.11.7471", VersionFormat::IntelMe2, Equal),
+            ("20230401", "20230330", VersionFormat::Number, Greater),
+            ("100", "99", VersionFormat::Number, Greater),
+            ("100", "99", VersionFormat::Plain, Less),
+            ("abc", "abd", VersionFormat::Unknown(0), Less),
+        ];
+
+        for &(a, b, format, expected) in CASES {
+            assert_eq!(compare_versions(a, b, format), expected, "{} vs {} ({:?})", a, b, format);
+        }
+    }
+
+    #[test]
+    fn raw_version_formats() {
+        const CASES: &[(u64, VersionFormat, &str)] = &[
+            (0x0102_0304, VersionFormat::Quad, "1.2.3.4"),
+            (0x0102_0304, VersionFormat::Triplet, "1.2.772"),
+            (0x0102_0304, VersionFormat::Pair, "258.772"),
+            (0x0102_0304, VersionFormat::Number, "16909060"),
+            (0x1234_5678, VersionFormat::Bcd, "12.34.56.78"),
+            (0x4A0B_1D2F, VersionFormat::IntelMe, "13.10.11.7471"),
+            (0x4A0B_1D2F, VersionFormat::IntelMe2, "4.10.11.7471"),
+            (0x0102_0304, VersionFormat::SurfaceLegacy, "4.128.772"),
+            (0x0102_0304, VersionFormat::Surface, "1.515.4"),
+            (0x0102_0304, VersionFormat::DellBios, "2.3.4"),
+            (0x0102_0304, VersionFormat::DellBiosMsb, "1.2.3"),
+            (0x0102_0304, VersionFormat::Hex, "0x01020304"),
+            (0x0102_0304, VersionFormat::Unknown(99), "16909060"),
+            (0x0001_0002_0003_0004, VersionFormat::Quad, "1.2.3.4"),
+            (0x0001_0002_0000_0003, VersionFormat::Triplet, "1.2.3"),
+            (0x0000_0001_0000_0002, VersionFormat::Pair, "1.2"),
+            (0x0000_0001_0000_0002, VersionFormat::Bcd, "4294967298"),
+        ];
+
+        for &(raw, format, expected) in CASES {
+            assert_eq!(format_version(raw, format), expected, "{:#x} ({:?})", raw, format);
+        }
+
+        let device = Device {
+            version_format: Some(VersionFormat::Triplet),
+            ..Device::try_from(dict(&[("VersionRaw", Value::from(0x0102_0003u64))])).unwrap()
+        };
+
+        assert_eq!(device.version_raw, Some(0x0102_0003));
+        assert_eq!(device.format_version(device.version_raw.unwrap()), "1.2.3");
+    }
+
+    #[test]
+    fn unknown_values_retained() {
+        let flags = DeviceFlags::from_bits_retain(1 << 60 | 1 << 1 | 1);
+        assert_eq!(flags.bits(), 1 << 60 | 1 << 1 | 1);
+        assert_eq!(flags.to_string(), "internal|updatable|0x1000000000000000");
+        assert_eq!(DeviceFlags::empty().to_string(), "none");
+        assert_eq!(DeviceFlags::AFFECTS_FDE.to_string(), "affects-fde");
+        assert_eq!(FeatureFlags::from_bits_retain(1 << 20).to_string(), "0x100000");
+
+        let device = Device::try_from(dict(&[
+            ("Flags", Value::from((1u64 << 60) | (1 << 53))),
+            ("Status", Value::from(99u64)),
+            ("UpdateState", Value::from(4u64)),
+            ("VersionFormat", Value::from(42u64)),
+        ]))
+        .unwrap();
+
+        assert_eq!(device.flags.bits(), 1 << 60 | 1 << 53);
+        assert_eq!(device.status, Some(Status::Unknown(99)));
+        assert_eq!(device.update_state, Some(UpdateState::NeedsReboot));
+        assert_eq!(device.version_format, Some(VersionFormat::Unknown(42)));
+
+        assert_eq!(Status::from(14).to_string(), "waiting-for-user");
+        assert_eq!(Status::from(99).to_string(), "unknown(99)");
+        assert_eq!(Status::from(0).to_string(), "unknown");
+        assert_eq!(UpdateState::FailedTransient.to_string(), "failed-transient");
+        assert_eq!(VersionFormat::from(13).to_string(), "dell-bios-msb");
+        assert_eq!(KeyringKind::from(4).to_string(), "jcat");
+        assert_eq!(RemoteKind::from(7), RemoteKind::Unknown(7));
+        assert_eq!(HsiResult::from(2).to_string(), "not-enabled");
+        assert_eq!(BiosSettingKind::from(9).to_string(), "unknown(9)");
+    }
+
+    #[test]
+    fn status_values() {
+        // The values and names of `FwupdStatus` in fwupd-enums.h.
+        const STATUSES: &[(u32, Status, &str)] = &[
+            (0, Status::Unknown(0), "unknown"),
+            (1, Status::Idle, "idle"),
+            (2, Status::Loading, "loading"),
+            (3, Status::Decompressing, "decompressing"),
+            (4, Status::DeviceRestart, "device-restart"),
+            (5, Status::DeviceWrite, "device-write"),
+            (6, Status::DeviceVerify, "device-verify"),
+            (7, Status::Scheduling, "scheduling"),
+            (8, Status::Downloading, "downloading"),
+            (9, Status::DeviceRead, "device-read"),
+            (10, Status::DeviceErase, "device-erase"),
+            (11, Status::WaitingForAuth, "waiting-for-auth"),
+            (12, Status::DeviceBusy, "device-busy"),
+            (13, Status::Shutdown, "shutdown"),
+            (14, Status::WaitingForUser, "waiting-for-user"),
+            (15, Status::Unknown(15), "unknown(15)"),
+        ];
+
+        for &(value, status, name) in STATUSES {
+            assert_eq!(Status::from(value), status, "{}", value);
+            assert_eq!(u32::from(status), value, "{:?}", status);
+            assert_eq!(status.to_string(), name, "{}", value);
+        }
+    }
+
+    #[test]
+    fn release_ordering() {
+        let release = |version: &str| Release { version: version.into(), ..Default::default() };
+
+        let mut releases = vec![release("1.10.0"), release("1.9.2"), release("1.9.10")];
+        releases.sort();
+
+        let versions: Vec<&str> = releases.iter().map(|release| &*release.version).collect();
+        assert_eq!(versions, ["1.9.2", "1.9.10", "1.10.0"]);
+
+        let device = Device {
+            version: "0x00010009".into(),
+            version_format: Some(VersionFormat::Pair),
+            ..Default::default()
+        };
+
+        assert!(device == release("1.9"));
+        assert!(device < release("1.10"));
+        assert!(device > release("1.8"));
+    }
+
+    #[test]
+    fn release_security_issues() {
+        let release = Release::try_from(dict(&[
+            ("Version", Value::from("1.2")),
+            ("Issues", Value::from(vec!["CVE-2022-21894", "DSA-2023-0001"])),
+            ("Urgency", Value::from(4u32)),
+            ("Tags", Value::from(vec!["vendor-2022q3"])),
+            ("ReportUri", Value::from("https://fwupd.org/lvfs/firmware/report")),
+            ("DetachCaption", Value::from("Unplug the device, then hold the button")),
+            ("DetachImage", Value::from("https://fwupd.org/img/detach.png")),
+        ]))
+        .unwrap();
+
+        assert_eq!(release.urgency, Urgency::Critical);
+        assert_eq!(release.urgency.to_string(), "critical");
+        assert_eq!(release.cves().collect::<Vec<_>>(), ["CVE-2022-21894"]);
+        assert!(release.fixes_cve("cve-2022-21894"));
+        assert!(!release.fixes_cve("CVE-2021-3156"));
+        assert!(release.is_security_update());
+        assert_eq!(&*release.tags, [Box::from("vendor-2022q3")]);
+        assert_eq!(release.report_uri.as_deref(), Some("https://fwupd.org/lvfs/firmware/report"));
+        assert!(release.detach_caption.is_some() && release.detach_image.is_some());
+        assert_eq!(Urgency::from(9), Urgency::Unknown(9));
+
+        let release = |version: &str, urgency| Release {
+            version: version.into(),
+            urgency,
+            ..Default::default()
+        };
+
+        let mut releases = vec![
+            release("1.2", Urgency::Medium),
+            release("2.0", Urgency::default()),
+            release("1.1", Urgency::Critical),
+            release("1.3", Urgency::Medium),
+            release("1.9", Urgency::Low),
+        ];
+        sort_by_urgency(&mut releases);
+
+        let versions: Vec<&str> = releases.iter().map(|release| &*release.version).collect();
+        assert_eq!(versions, ["1.1", "1.3", "1.2", "1.9", "2.0"]);
+    }
+
+    #[cfg(feature = "mock")]
+    const MOCK_DEVICE_ID: &str = "1a8d0d9a96ad3e67ba76cf3033623625dc6d6882";
+
+    #[cfg(feature = "mock")]
+    fn mock_daemon() -> (mock::MockBus, mock::MockDaemon, Client) {
+        let bus = mock::MockBus::start().expect("dbus-daemon is required for mock tests");
+        let daemon = mock::MockDaemon::start(&bus).unwrap();
+        daemon.add_device(dict(&[
+            ("DeviceId", Value::from(MOCK_DEVICE_ID)),
+            ("Name", Value::from("Thunderbolt Controller")),
+            ("Version", Value::from("1.9")),
+        ]));
+
+        let client = Client::from_address(bus.address()).unwrap();
+        (bus, daemon, client)
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn mock_queries() {
+        let (_bus, daemon, client) = mock_daemon();
+        daemon.add_upgrade(MOCK_DEVICE_ID, dict(&[("Version", Value::from("1.10"))]));
+        daemon
+            .add_remote(dict(&[("RemoteId", Value::from("lvfs")), ("Enabled", Value::from(true))]));
+
+        assert_eq!(client.client_name, "fwupd/1.9.0");
+
+        let devices = client.devices().unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(&*devices[0].name, "Thunderbolt Controller");
+
+        let upgrades = client.upgrades(&devices[0]).unwrap();
+        assert!(devices[0] < upgrades[0]);
+
+        let remotes = client.remotes().unwrap();
+        assert_eq!(&**remotes[0].remote_id, "lvfs");
+        assert!(client.remote(&remotes[0]).unwrap().enabled);
+
+        let missing = client.releases(Device::default());
+        assert!(matches!(missing, Err(Error::Call("GetReleases", _))));
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn mock_actions() {
+        let (_bus, daemon, client) = mock_daemon();
+        daemon.fail("FixHostSecurityAttr", "NotSupported");
+
+        let firmware = std::env::temp_dir().join("fwupd-dbus-mock-firmware.cab");
+        fs::write(&firmware, b"firmware").unwrap();
+        let device = &client.devices().unwrap()[0];
+        let flags = InstallFlags::ALLOW_REINSTALL;
+        client.install(device, "", &firmware, None::<File>, flags).unwrap();
+        let _ = fs::remove_file(&firmware);
+
+        let installs = daemon.installs();
+        assert_eq!(&*installs[0].device_id, MOCK_DEVICE_ID);
+        assert_eq!(installs[0].firmware, b"firmware");
+        assert_eq!(*installs[0].options["allow-reinstall"], Value::Bool(true));
+
+        let fixed = client.fix_host_security_attr("org.fwupd.hsi.Kernel.Lockdown");
+        assert!(matches!(fixed, Err(Error::NotSupported("FixHostSecurityAttr", _))));
+
+        let calls = daemon.calls();
+        let last = calls.last().unwrap();
+        assert_eq!(&*last.method, "FixHostSecurityAttr");
+        assert_eq!(last.args, ["org.fwupd.hsi.Kernel.Lockdown"]);
+    }
+
+    /// Serves files from memory in place of a remote, recording the offsets of requests.
+    #[cfg(feature = "mock")]
+    #[derive(Clone, Default)]
+    struct StubDownloader {
+        files:   HashMap<&'static str, &'static [u8]>,
+        offsets: Arc<std::sync::Mutex<Vec<u64>>>,
+    }
+
+    #[cfg(feature = "mock")]
+    impl StubDownloader {
+        fn new(uri: &'static str, data: &'static [u8]) -> Self {
+            Self { files: HashMap::from([(uri, data)]), ..Self::default() }
+        }
+    }
+
+    #[cfg(feature = "mock")]
+    impl Downloader for StubDownloader {
+        fn download(&self, request: &DownloadRequest) -> Result<Download, DownloadError> {
+            self.offsets.lock().unwrap().push(request.offset);
+            let data = self.files.get(request.uri).ok_or("file not found")?;
+            let offset = request.offset.min(data.len() as u64);
+            Ok(Download::new(Box::new(&data[offset as usize..])).with_offset(offset))
+        }
+    }
+
+    /// Keeps files which tests download out of the cache of the user.
+    #[cfg(feature = "mock")]
+    fn mock_cache() -> PathBuf { std::env::temp_dir().join("fwupd-dbus-tests") }
+
+    #[cfg(feature = "mock")]
+    fn mock_release(uri: &str, data: &[u8]) -> Release {
+        use crypto_hash::{hex_digest, Algorithm};
+
+        Release {
+            checksums: vec![Box::from(hex_digest(Algorithm::SHA256, data))].into(),
+            remote_id: RemoteId("lvfs".into()),
+            uri: uri.into(),
+            ..Default::default()
+        }
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn mock_downloader() {
+        const URI: &str = "https://cdn.example.com/downloads/downloader-firmware.cab";
+
+        let (bus, daemon, _) = mock_daemon();
+        daemon.add_remote(dict(&[("RemoteId", Value::from("lvfs"))]));
+
+        let client = Client::builder()
+            .address(bus.address())
+            .downloader(StubDownloader::new(URI, b"firmware"))
+            .firmware_cache(mock_cache())
+            .build()
+            .unwrap();
+
+        let release = mock_release(URI, b"firmware");
+        let _ = fs::remove_file(client.firmware_cache_path(&URI.parse().unwrap()).unwrap());
+
+        let (_, file) = client
+            .fetch_firmware_from_release(&Device::default(), &release, None, None::<fn(FlashEvent)>)
+            .unwrap();
+
+        let mut data = Vec::new();
+        file.unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"firmware");
+
+        let missing = mock_release("https://cdn.example.com/downloads/missing.cab", b"");
+        let result = client.fetch_firmware_from_release(
+            &Device::default(),
+            &missing,
+            None,
+            None::<fn(FlashEvent)>,
+        );
+        assert!(matches!(result, Err(Error::FirmwareGet(_))));
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn mock_mirrors() {
+        const MIRROR: &str = "https://mirror.example.com/downloads/mirrored-firmware.cab";
+
+        let (bus, daemon, _) = mock_daemon();
+        daemon.add_remote(dict(&[("RemoteId", Value::from("lvfs"))]));
+
+        let client = Client::builder()
+            .address(bus.address())
+            .downloader(StubDownloader::new(MIRROR, b"firmware"))
+            .firmware_cache(mock_cache())
+            .build()
+            .unwrap();
+
+        let missing = "https://cdn.example.com/downloads/mirrored-firmware.cab";
+        let release = Release {
+            locations: vec![Box::from(missing), Box::from(MIRROR)].into(),
+            ..mock_release("", b"firmware")
+        };
+
+        let _ = fs::remove_file(client.firmware_cache_path(&missing.parse().unwrap()).unwrap());
+
+        let mut mirrors = Vec::new();
+        let callback = |event| {
+            if let FlashEvent::DownloadedFrom(uri) = event {
+                mirrors.push(uri);
+            }
+        };
+
+        let (path, _) = client
+            .fetch_firmware_from_release(&Device::default(), &release, None, Some(callback))
+            .unwrap();
+
+        assert_eq!(mirrors, [Box::from(MIRROR)]);
+        assert_eq!(fs::read(path).unwrap(), b"firmware");
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn mock_directory_remote() {
+        let (_bus, daemon, client) = mock_daemon();
+        daemon.add_remote(dict(&[
+            ("RemoteId", Value::from("vendor-directory")),
+            ("Type", Value::from(3u32)),
+        ]));
+
+        let fetch = |locations: &[&str]| {
+            let release = Release {
+                locations: locations.iter().map(|&location| Box::from(location)).collect(),
+                remote_id: RemoteId("vendor-directory".into()),
+                ..Default::default()
+            };
+
+            client.fetch_firmware_from_release(
+                &Device::default(),
+                &release,
+                None,
+                None::<fn(FlashEvent)>,
+            )
+        };
+
+        let (path, file) = fetch(&["file:///usr/share/fwupd/vendor/firmware.cab"]).unwrap();
+        assert_eq!(path, Path::new("/usr/share/fwupd/vendor/firmware.cab"));
+        assert!(file.is_none());
+
+        assert!(matches!(fetch(&[]), Err(Error::ReleaseWithoutLocations)));
+        match fetch(&["a.cab"]) {
+            Err(Error::ReleaseLocation(location)) => assert_eq!(&*location, "a.cab"),
+            other => panic!("expected invalid release location, found {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn mock_resumed_download() {
+        const URI: &str = "https://cdn.example.com/downloads/resumed-firmware.cab";
+
+        let (bus, daemon, _) = mock_daemon();
+        daemon.add_remote(dict(&[("RemoteId", Value::from("lvfs"))]));
+
+        let downloader = StubDownloader::new(URI, b"firmware");
+        let client = Client::builder()
+            .address(bus.address())
+            .downloader(downloader.clone())
+            .firmware_cache(mock_cache())
+            .build()
+            .unwrap();
+
+        let release = mock_release(URI, b"firmware");
+        let cache = client.firmware_cache_path(&URI.parse().unwrap()).unwrap();
+        fs::write(&cache, b"firm").unwrap();
+
+        let mut progress = Vec::new();
+        let callback = |event| {
+            if let FlashEvent::DownloadUpdate(bytes) = event {
+                progress.push(bytes);
+            }
+        };
+
+        let (_, file) = client
+            .fetch_firmware_from_release(&Device::default(), &release, None, Some(callback))
+            .unwrap();
+
+        let mut data = Vec::new();
+        file.unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"firmware");
+        assert_eq!(*downloader.offsets.lock().unwrap(), [4]);
+        assert_eq!(progress, [8]);
+
+        // A corrupt partial download is downloaded again from the start of the same mirror.
+        fs::write(&cache, b"farm").unwrap();
+        let (_, file) = client
+            .fetch_firmware_from_release(&Device::default(), &release, None, None::<fn(FlashEvent)>)
+            .unwrap();
+
+        let mut data = Vec::new();
+        file.unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"firmware");
+        assert_eq!(*downloader.offsets.lock().unwrap(), [4, 4, 0]);
+
+        // The download is discarded if the firmware of the mirror is invalid as well.
+        fs::write(&cache, b"farm").unwrap();
+        let result = client.fetch_firmware_from_release(
+            &Device::default(),
+            &mock_release(URI, b"other firmware"),
+            None,
+            None::<fn(FlashEvent)>,
+        );
+        assert!(matches!(result, Err(Error::FirmwareChecksumMismatch)));
+        assert_eq!(*downloader.offsets.lock().unwrap(), [4, 4, 0, 4, 0]);
+        assert!(!cache.exists());
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn mock_cancelled_download() {
+        const URI: &str = "https://cdn.example.com/downloads/cancelled-firmware.cab";
+        const FIRMWARE: &[u8] = &[0xff; 32 * 1024];
+
+        let (bus, daemon, _) = mock_daemon();
+        daemon.add_remote(dict(&[("RemoteId", Value::from("lvfs"))]));
+
+        let client = Client::builder()
+            .address(bus.address())
+            .downloader(StubDownloader::new(URI, FIRMWARE))
+            .firmware_cache(mock_cache())
+            .build()
+            .unwrap();
+
+        let release = mock_release(URI, FIRMWARE);
+        let cache = client.firmware_cache_path(&URI.parse().unwrap()).unwrap();
+        let _ = fs::remove_file(&cache);
+
+        // A copy which is cancelled after its first chunk stops at what it has reported.
+        let cancellable = Cancellable::new();
+        let (mut output, mut copied) = (Vec::new(), 0);
+        let completed =
+            download::copy_download(&mut &*FIRMWARE, &mut output, Some(&cancellable), |read| {
+                copied += read;
+                cancellable.cancel();
+            });
+        assert!(!completed.unwrap());
+        assert_eq!(output.len(), copied);
+        assert!(output.len() < FIRMWARE.len());
+
+        // Cancel from the callback once the first chunk has arrived.
+        let cancellable = Cancellable::new();
+        let mut progress = Vec::new();
+        let callback = |event| {
+            if let FlashEvent::DownloadUpdate(bytes) = event {
+                progress.push(bytes);
+                cancellable.cancel();
+            }
+        };
+
+        let result = client.fetch_firmware_from_release(
+            &Device::default(),
+            &release,
+            Some(&cancellable),
+            Some(callback),
+        );
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert!(matches!(progress[..], [bytes] if bytes < FIRMWARE.len()));
+        assert!(!cache.exists());
+
+        // Nothing is installed once an update has been cancelled.
+        let device = &client.devices().unwrap()[0];
+        let result = client.update_device_with_release(
+            device,
+            &release,
+            InstallFlags::empty(),
+            Some(&cancellable),
+            None::<fn(FlashEvent)>,
+        );
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert!(daemon.installs().is_empty());
+
+        // Without cancellation, the download runs to the end of the firmware.
+        let mut progress = Vec::new();
+        let callback = |event| {
+            if let FlashEvent::DownloadUpdate(bytes) = event {
+                progress.push(bytes);
+            }
+        };
+
+        client
+            .fetch_firmware_from_release(&Device::default(), &release, None, Some(callback))
+            .unwrap();
+        assert_eq!(progress.last(), Some(&FIRMWARE.len()));
+        assert_eq!(fs::read(&cache).unwrap(), FIRMWARE);
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn mock_install_progress() {
+        const URI: &str = "https://cdn.example.com/downloads/progress-firmware.cab";
+
+        let (bus, daemon, _) = mock_daemon();
+        daemon.add_remote(dict(&[("RemoteId", Value::from("lvfs"))]));
+        // The raw values of `FwupdStatus`, as the daemon sends them.
+        daemon.set_install_progress(&[
+            (11, 0),
+            (10, 0),
+            (5, 0),
+            (5, 50),
+            (5, 100),
+            (6, 100),
+            (9, 100),
+            (1, 0),
+        ]);
+
+        let client = Client::builder()
+            .address(bus.address())
+            .downloader(StubDownloader::new(URI, b"firmware"))
+            .firmware_cache(mock_cache())
+            .build()
+            .unwrap();
+
+        let mut events = Vec::new();
+        let callback = |event| match event {
+            FlashEvent::DownloadUpdate(_) | FlashEvent::DownloadInitiate(_) => (),
+            event => events.push(event),
+        };
+
+        let device = &client.devices().unwrap()[0];
+        let release = mock_release(URI, b"firmware");
+        let _ = fs::remove_file(client.firmware_cache_path(&URI.parse().unwrap()).unwrap());
+        client
+            .update_device_with_release(
+                device,
+                &release,
+                InstallFlags::empty(),
+                None,
+                Some(callback),
+            )
+            .unwrap();
+
+        assert_eq!(
+            events,
+            [
+                FlashEvent::DownloadComplete,
+                FlashEvent::VerifyingChecksum,
+                FlashEvent::DownloadedFrom(URI.into()),
+                FlashEvent::FlashInProgress,
+                FlashEvent::WaitingForAuth(0),
+                FlashEvent::DeviceErase(0),
+                FlashEvent::DeviceWrite(0),
+                FlashEvent::DeviceWrite(50),
+                FlashEvent::DeviceWrite(100),
+                FlashEvent::DeviceVerify(100),
+                FlashEvent::DeviceRead(100),
+            ]
+        );
+        assert_eq!(daemon.installs()[0].firmware, b"firmware");
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn mock_switch_branch() {
+        use crypto_hash::{hex_digest, Algorithm};
+
+        const URI: &str = "https://cdn.example.com/downloads/community-firmware.cab";
+
+        let (bus, daemon, _) = mock_daemon();
+        daemon.add_remote(dict(&[("RemoteId", Value::from("lvfs"))]));
+
+        let checksum = hex_digest(Algorithm::SHA256, b"community");
+        let release = |version: &str, branch: Option<&str>, uri: &str| {
+            let mut release = dict(&[
+                ("Version", Value::from(version)),
+                ("Checksum", Value::from(checksum.as_str())),
+                ("RemoteId", Value::from("lvfs")),
+                ("Uri", Value::from(uri)),
+            ]);
+
+            if let Some(branch) = branch {
+                release.insert("Branch".into(), Value::from(branch).into());
+            }
+
+            release
+        };
+
+        for (version, branch, uri) in [
+            ("1.9", None, "https://cdn.example.com/downloads/1.9.cab"),
+            ("1.10", None, "https://cdn.example.com/downloads/1.10.cab"),
+            ("0.5", Some("community"), "https://cdn.example.com/downloads/0.5.cab"),
+            ("0.7", Some("community"), URI),
+        ] {
+            daemon.add_release(MOCK_DEVICE_ID, release(version, branch, uri));
+        }
+
+        let client = Client::builder()
+            .address(bus.address())
+            .downloader(StubDownloader::new(URI, b"community"))
+            .firmware_cache(mock_cache())
+            .build()
+            .unwrap();
+
+        let device = &client.devices().unwrap()[0];
+        let branches = client.branches(device).unwrap();
+        let versions = |branch: Option<&str>| {
+            branches[&branch.map(Box::from)].iter().map(|r| &*r.version).collect::<Vec<_>>()
+        };
+
+        assert_eq!(branches.len(), 2);
+        assert_eq!(versions(None), ["1.10", "1.9"]);
+        assert_eq!(versions(Some("community")), ["0.7", "0.5"]);
+
+        let current = client.switch_branch(device, None, None, None::<fn(FlashEvent)>);
+        assert!(
+            matches!(current, Err(Error::BranchAlreadySelected(ref name)) if &**name == "default")
+        );
+
+        let missing = client.switch_branch(device, Some("beta"), None, None::<fn(FlashEvent)>);
+        assert!(matches!(missing, Err(Error::BranchNotFound(ref name)) if &**name == "beta"));
+
+        let _ = fs::remove_file(client.firmware_cache_path(&URI.parse().unwrap()).unwrap());
+        let release =
+            client.switch_branch(device, Some("community"), None, None::<fn(FlashEvent)>).unwrap();
+        assert_eq!(&*release.version, "0.7");
+        assert_eq!(release.branch.as_deref(), Some("community"));
+
+        let installs = daemon.installs();
+        assert_eq!(installs[0].firmware, b"community");
+        for option in ["allow-branch-switch", "allow-older", "allow-reinstall"] {
+            assert_eq!(*installs[0].options[option], Value::Bool(true), "{}", option);
+        }
+    }
+
+    #[cfg(all(feature = "async", feature = "mock"))]
+    #[test]
+    fn mock_async_switch_branch() {
+        let (bus, daemon, _) = mock_daemon();
+        let releases = [("1.10", None), ("0.5", Some("community")), ("0.7", Some("community"))];
+        for (version, branch) in releases {
+            let mut release = dict(&[("Version", Value::from(version))]);
+            if let Some(branch) = branch {
+                release.insert("Branch".into(), Value::from(branch).into());
+            }
+
+            daemon.add_release(MOCK_DEVICE_ID, release);
+        }
+
+        let firmware = std::env::temp_dir().join("fwupd-dbus-mock-community.cab");
+        fs::write(&firmware, b"community").unwrap();
+
+        zbus::block_on(async {
+            let client = AsyncClient::from_address(bus.address()).await.unwrap();
+            let device = &client.devices().await.unwrap()[0];
+
+            let mut fetched = Vec::new();
+            let mut fetch = |release: &Release| {
+                fetched.push(release.version.clone());
+                future::ready(Ok((firmware.clone(), None::<File>)))
+            };
+
+            match client.switch_branch(device, None, &mut fetch).await {
+                Err(Error::BranchAlreadySelected(name)) => assert_eq!(&*name, "default"),
+                other => panic!("expected the branch to be selected, found {:?}", other),
+            }
+
+            let missing = client.switch_branch(device, Some("beta"), &mut fetch).await;
+            assert!(matches!(missing, Err(Error::BranchNotFound(ref name)) if &**name == "beta"));
+
+            let release =
+                client.switch_branch(device, Some("community"), &mut fetch).await.unwrap();
+            assert_eq!(&*release.version, "0.7");
+            assert_eq!(fetched, [Box::from("0.7")]);
+        });
+
+        let _ = fs::remove_file(&firmware);
+
+        let installs = daemon.installs();
+        assert_eq!(installs[0].firmware, b"community");
+        for option in ["allow-branch-switch", "allow-older", "allow-reinstall"] {
+            assert_eq!(*installs[0].options[option], Value::Bool(true), "{}", option);
+        }
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn mock_timeouts() {
+        let (bus, daemon, _) = mock_daemon();
+        daemon.delay("GetDevices", Duration::from_millis(500));
+        daemon.delay("GetPlugins", Duration::from_millis(500));
+
+        let client = Client::builder()
+            .address(bus.address())
+            .client_name("fleet-agent")
+            .timeout(Duration::from_millis(100))
+            .method_timeout("GetPlugins", None)
+            .build()
+            .unwrap();
+
+        assert_eq!(client.client_name, "fleet-agent");
+        assert!(matches!(client.devices(), Err(Error::Timeout("GetDevices"))));
+
+        // The daemon keeps serving other requests while the call which timed out is delayed.
+        let started = std::time::Instant::now();
+        assert_eq!(&*client.daemon_version().unwrap(), "1.9.0");
+        assert!(started.elapsed() < Duration::from_millis(300));
+
+        assert!(client.plugins().unwrap().is_empty());
+        assert!(matches!(client.releases(Device::default()), Err(Error::Call("GetReleases", _))));
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn mock_property_timeouts() {
+        let (bus, daemon, _) = mock_daemon();
+        daemon.delay("DaemonVersion", Duration::from_millis(500));
+
+        let client = Client::builder()
+            .address(bus.address())
+            .client_name("fleet-agent")
+            .timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+
+        assert!(matches!(client.daemon_version(), Err(Error::Timeout("DaemonVersion"))));
+        assert!(!client.tainted().unwrap());
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn mock_async_timeouts() {
+        let (bus, daemon, _) = mock_daemon();
+        daemon.delay("GetDevices", Duration::from_millis(500));
+        daemon.delay("GetPlugins", Duration::from_millis(500));
+        daemon.delay("DaemonVersion", Duration::from_millis(500));
+
+        zbus::block_on(async {
+            let client = AsyncClient::from_address(bus.address())
+                .await
+                .unwrap()
+                .timeout(Duration::from_millis(100))
+                .method_timeout("GetPlugins", None);
+
+            assert!(matches!(client.devices().await, Err(Error::Timeout("GetDevices"))));
+            assert!(matches!(client.daemon_version().await, Err(Error::Timeout("DaemonVersion"))));
+            assert!(client.plugins().await.unwrap().is_empty());
+        });
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn mock_signals() {
+        let (_bus, daemon, client) = mock_daemon();
+        let cancellable = Cancellable::new();
+        let mut signals = client.listen_signals(&cancellable).unwrap();
+
+        daemon.emit_device_added(dict(&[("Name", Value::from("Dock"))])).unwrap();
+        daemon.set_status(u32::from(Status::Decompressing)).unwrap();
+
+        match signals.next() {
+            Some(Signal::DeviceAdded(device)) => assert_eq!(&*device.name, "Dock"),
+            other => panic!("expected DeviceAdded signal, found {:?}", other),
+        }
+
+        match signals.next() {
+            Some(Signal::PropertiesChanged { changed, .. }) => {
+                assert_eq!(*changed["Status"], Value::U32(u32::from(Status::Decompressing)))
+            }
+            other => panic!("expected PropertiesChanged signal, found {:?}", other),
+        }
+
+        cancellable.cancel();
+        daemon.emit_device_added(dict(&[("Name", Value::from("Dock"))])).unwrap();
+        assert!(signals.next().is_none());
+    }
+
     #[test]
     fn remote_baseuri() {
         let remote = download_remote();
-        let firmware_uri = remote.firmware_uri("http://bbc.co.uk/firmware.cab");
+        let firmware_uri = remote.firmware_uri("http://bbc.co.uk/firmware.cab").unwrap();
         assert_eq!(firmware_uri.to_string().as_str(), "https://my.fancy.cdn/firmware.cab")
     }
 
     #[test]
     fn remote_nopath() {
         let remote = nopath_remote();
-        let firmware_uri = remote.firmware_uri("firmware.cab");
+        let firmware_uri = remote.firmware_uri("firmware.cab").unwrap();
         assert_eq!(
             firmware_uri.to_string().as_str(),
             "https://s3.amazonaws.com/lvfsbucket/downloads/firmware.cab"
         )
     }
+
+    #[test]
+    fn remote_mirrors() {
+        let release = Release {
+            locations: vec![
+                Box::from("https://fwupd.org/downloads/firmware.cab"),
+                Box::from("https://mirror.example.com/firmware.cab"),
+            ]
+            .into(),
+            uri: "https://fwupd.org/downloads/firmware.cab".into(),
+            ..Default::default()
+        };
+
+        let uris = download_remote().firmware_uris(&release);
+        assert_eq!(
+            uris.iter().map(url::Url::as_str).collect::<Vec<_>>(),
+            [
+                "https://my.fancy.cdn/firmware.cab",
+                "https://fwupd.org/downloads/firmware.cab",
+                "https://mirror.example.com/firmware.cab",
+            ]
+        );
+
+        let uris =
+            nopath_remote().firmware_uris(&Release { uri: "firmware.cab".into(), ..release });
+        assert_eq!(
+            uris.iter().map(url::Url::as_str).collect::<Vec<_>>(),
+            [
+                "https://fwupd.org/downloads/firmware.cab",
+                "https://mirror.example.com/firmware.cab",
+                "https://s3.amazonaws.com/lvfsbucket/downloads/firmware.cab",
+            ]
+        );
+
+        // Mirrors which do not form a valid URI are skipped, rather than failing the release.
+        let release = Release {
+            locations: vec![
+                Box::from("downloads/relative.cab"),
+                Box::from("https://mirror.example.com/firmware.cab"),
+            ]
+            .into(),
+            ..Default::default()
+        };
+
+        let remote = Remote { uri: None, ..nopath_remote() };
+        assert_eq!(remote.firmware_uri("firmware.cab"), None);
+        assert_eq!(
+            remote.firmware_uris(&release).iter().map(url::Url::as_str).collect::<Vec<_>>(),
+            ["https://mirror.example.com/firmware.cab"]
+        );
+    }
 }
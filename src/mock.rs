@@ -0,0 +1,578 @@
+//! A fake fwupd daemon, for testing clients without a live system daemon.
+//!
+//! [`MockBus`] starts a private bus with `dbus-daemon`, which must be in the `PATH`, and
+//! [`MockDaemon`] registers a scripted `org.freedesktop.fwupd` service on it. Clients connect
+//! to the bus with [`Client::from_address`](crate::Client::from_address).
+//!
+//! ```no_run
+//! use fwupd_dbus::{mock::*, Client};
+//! use zbus::zvariant::Value;
+//!
+//! let bus = MockBus::start().unwrap();
+//! let daemon = MockDaemon::start(&bus).unwrap();
+//! daemon.add_device(mock_dict(&[
+//!     ("DeviceId", Value::from("1a8d0d9a96ad3e67ba76cf3033623625dc6d6882")),
+//!     ("Name", Value::from("Thunderbolt Controller")),
+//! ]));
+//!
+//! let client = Client::from_address(bus.address()).unwrap();
+//! assert_eq!(client.devices().unwrap().len(), 1);
+//! ```
+
+use crate::{DBusDict, DBUS_IFACE, DBUS_NAME, DBUS_PATH};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    os::unix::io::{AsRawFd, BorrowedFd},
+    process::{Child, Command, Stdio},
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+use zbus::{
+    blocking::{Connection, ConnectionBuilder},
+    dbus_interface,
+    export::serde::Serialize,
+    names::ErrorName,
+    zvariant::{DynamicType, Fd, OwnedValue, Value},
+    DBusError, Message, MessageBuilder, MessageHeader,
+};
+
+/// Builds a dictionary of the daemon from pairs of keys and values.
+pub fn mock_dict(entries: &[(&str, Value)]) -> DBusDict {
+    entries
+        .iter()
+        .map(|(key, value)| (String::from(*key), OwnedValue::from(value.clone())))
+        .collect()
+}
+
+/// A private bus, which is shut down when dropped.
+pub struct MockBus {
+    address: String,
+    daemon:  Child,
+}
+
+impl MockBus {
+    /// Starts a private session bus with `dbus-daemon`.
+    pub fn start() -> io::Result<Self> {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let mut address = String::new();
+        let stdout = daemon.stdout.take().expect("dbus-daemon without stdout");
+        if let Err(why) = BufReader::new(stdout).read_line(&mut address) {
+            let _ = daemon.kill();
+            return Err(why);
+        }
+
+        Ok(Self { address: address.trim().to_owned(), daemon })
+    }
+
+    /// The address which clients connect to.
+    pub fn address(&self) -> &str { &self.address }
+}
+
+impl Drop for MockBus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
+
+/// A method call which the mock daemon received.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MockCall {
+    pub method: Box<str>,
+    /// The string arguments of the call, such as the ID of a device.
+    pub args:   Vec<String>,
+}
+
+/// A firmware which was installed through the mock daemon.
+#[derive(Clone, Debug)]
+pub struct MockInstall {
+    pub device_id: Box<str>,
+    pub firmware:  Vec<u8>,
+    pub options:   HashMap<String, OwnedValue>,
+}
+
+#[derive(Default)]
+struct State {
+    approved_firmware: Vec<String>,
+    bios_settings:     Vec<DBusDict>,
+    blocked_firmware:  Vec<String>,
+    calls:             Vec<MockCall>,
+    daemon_version:    String,
+    delays:            HashMap<String, Duration>,
+    devices:           Vec<DBusDict>,
+    downgrades:        HashMap<String, Vec<DBusDict>>,
+    errors:            HashMap<String, String>,
+    host_security_id:  String,
+    install_progress:  Vec<(u32, u32)>,
+    installs:          Vec<MockInstall>,
+    percentage:        u32,
+    plugins:           Vec<DBusDict>,
+    releases:          HashMap<String, Vec<DBusDict>>,
+    remotes:           Vec<DBusDict>,
+    results:           HashMap<String, DBusDict>,
+    security_attrs:    Vec<DBusDict>,
+    security_events:   Vec<DBusDict>,
+    status:            u32,
+    tainted:           bool,
+    upgrades:          HashMap<String, Vec<DBusDict>>,
+}
+
+impl State {
+    /// Records a call, returning the delay which was scripted for the method.
+    fn record(&mut self, method: &str, args: &[&str]) -> Option<Duration> {
+        self.calls.push(MockCall {
+            method: method.into(),
+            args:   args.iter().map(|&arg| arg.to_owned()).collect(),
+        });
+
+        self.delays.get(method).copied()
+    }
+}
+
+fn dict_str<'a>(dict: &'a DBusDict, key: &str) -> Option<&'a str> {
+    dict.get(key).and_then(|value| match &**value {
+        Value::Str(string) => Some(string.as_str()),
+        _ => None,
+    })
+}
+
+/// An error of the daemon, with a name in the `org.freedesktop.fwupd` namespace.
+#[derive(Debug)]
+struct MethodError {
+    name:        String,
+    description: String,
+}
+
+impl MethodError {
+    fn new(error: &str, method: &str) -> Self {
+        Self {
+            name:        [DBUS_IFACE, ".", error].concat(),
+            description: ["mock daemon failed ", method].concat(),
+        }
+    }
+}
+
+impl DBusError for MethodError {
+    fn create_reply(&self, header: &MessageHeader<'_>) -> zbus::Result<Message> {
+        MessageBuilder::error(header, self.name.as_str())?.build(&(self.description.as_str(),))
+    }
+
+    fn name(&self) -> ErrorName<'_> { ErrorName::from_str_unchecked(&self.name) }
+
+    fn description(&self) -> Option<&str> { Some(&self.description) }
+}
+
+/// Reads the whole contents of a file descriptor that was passed to the daemon.
+fn read_fd(fd: Fd) -> Vec<u8> {
+    let mut data = Vec::new();
+    let owned = unsafe { BorrowedFd::borrow_raw(fd.as_raw_fd()) }.try_clone_to_owned();
+    if let Ok(owned) = owned {
+        let _ = File::from(owned).read_to_end(&mut data);
+    }
+
+    data
+}
+
+/// The `org.freedesktop.fwupd` interface served by the mock daemon.
+struct Interface {
+    state: Arc<Mutex<State>>,
+}
+
+impl Interface {
+    fn state(&self) -> MutexGuard<State> { self.state.lock().expect("mock daemon state poisoned") }
+
+    /// Records a call and waits out its delay, failing if an error was scripted for the method.
+    ///
+    /// The state is not locked during the delay, so that other calls are served meanwhile.
+    async fn call(&self, method: &str, args: &[&str]) -> Result<MutexGuard<State>, MethodError> {
+        let delay = self.state().record(method, args);
+        if let Some(delay) = delay {
+            crate::sleep(delay).await;
+        }
+
+        let state = self.state();
+        match state.errors.get(method) {
+            Some(error) => Err(MethodError::new(error, method)),
+            None => Ok(state),
+        }
+    }
+
+    /// Waits out the delay of a property before it is read.
+    async fn read(&self, property: &str) -> MutexGuard<State> {
+        let delay = self.state().delays.get(property).copied();
+        if let Some(delay) = delay {
+            crate::sleep(delay).await;
+        }
+
+        self.state()
+    }
+
+    async fn action(&self, method: &str, id: &str) -> Result<(), MethodError> {
+        self.call(method, &[id]).await.map(drop)
+    }
+
+    async fn device_dicts(
+        &self,
+        method: &str,
+        device_id: &str,
+        kind: fn(&State) -> &HashMap<String, Vec<DBusDict>>,
+    ) -> Result<Vec<DBusDict>, MethodError> {
+        let state = self.call(method, &[device_id]).await?;
+
+        if !state.devices.iter().any(|device| dict_str(device, "DeviceId") == Some(device_id)) {
+            return Err(MethodError::new("NotFound", method));
+        }
+
+        Ok(kind(&state).get(device_id).cloned().unwrap_or_default())
+    }
+}
+
+#[dbus_interface(name = "org.freedesktop.fwupd")]
+impl Interface {
+    #[dbus_interface(property)]
+    async fn daemon_version(&self) -> String {
+        self.read("DaemonVersion").await.daemon_version.clone()
+    }
+
+    #[dbus_interface(property)]
+    async fn host_security_id(&self) -> String {
+        self.read("HostSecurityId").await.host_security_id.clone()
+    }
+
+    #[dbus_interface(property)]
+    async fn percentage(&self) -> u32 { self.read("Percentage").await.percentage }
+
+    #[dbus_interface(property)]
+    async fn status(&self) -> u32 { self.read("Status").await.status }
+
+    #[dbus_interface(property)]
+    async fn tainted(&self) -> bool { self.read("Tainted").await.tainted }
+
+    async fn activate(&self, id: &str) -> Result<(), MethodError> {
+        self.action("Activate", id).await
+    }
+
+    async fn clear_results(&self, id: &str) -> Result<(), MethodError> {
+        self.action("ClearResults", id).await
+    }
+
+    async fn fix_host_security_attr(&self, id: &str) -> Result<(), MethodError> {
+        self.action("FixHostSecurityAttr", id).await
+    }
+
+    async fn get_approved_firmware(&self) -> Result<Vec<String>, MethodError> {
+        Ok(self.call("GetApprovedFirmware", &[]).await?.approved_firmware.clone())
+    }
+
+    async fn get_bios_settings(&self) -> Result<Vec<DBusDict>, MethodError> {
+        Ok(self.call("GetBiosSettings", &[]).await?.bios_settings.clone())
+    }
+
+    async fn get_blocked_firmware(&self) -> Result<Vec<String>, MethodError> {
+        Ok(self.call("GetBlockedFirmware", &[]).await?.blocked_firmware.clone())
+    }
+
+    async fn get_devices(&self) -> Result<Vec<DBusDict>, MethodError> {
+        Ok(self.call("GetDevices", &[]).await?.devices.clone())
+    }
+
+    async fn get_downgrades(&self, id: &str) -> Result<Vec<DBusDict>, MethodError> {
+        self.device_dicts("GetDowngrades", id, |state| &state.downgrades).await
+    }
+
+    async fn get_host_security_attrs(&self) -> Result<Vec<DBusDict>, MethodError> {
+        Ok(self.call("GetHostSecurityAttrs", &[]).await?.security_attrs.clone())
+    }
+
+    async fn get_host_security_events(&self, limit: u32) -> Result<Vec<DBusDict>, MethodError> {
+        let state = self.call("GetHostSecurityEvents", &[&limit.to_string()]).await?;
+        Ok(state.security_events.iter().take(limit as usize).cloned().collect())
+    }
+
+    async fn get_plugins(&self) -> Result<Vec<DBusDict>, MethodError> {
+        Ok(self.call("GetPlugins", &[]).await?.plugins.clone())
+    }
+
+    async fn get_releases(&self, id: &str) -> Result<Vec<DBusDict>, MethodError> {
+        self.device_dicts("GetReleases", id, |state| &state.releases).await
+    }
+
+    async fn get_remotes(&self) -> Result<Vec<DBusDict>, MethodError> {
+        Ok(self.call("GetRemotes", &[]).await?.remotes.clone())
+    }
+
+    async fn get_results(&self, id: &str) -> Result<DBusDict, MethodError> {
+        let state = self.call("GetResults", &[id]).await?;
+        state.results.get(id).cloned().ok_or_else(|| MethodError::new("NothingToDo", "GetResults"))
+    }
+
+    async fn get_upgrades(&self, id: &str) -> Result<Vec<DBusDict>, MethodError> {
+        self.device_dicts("GetUpgrades", id, |state| &state.upgrades).await
+    }
+
+    async fn install(
+        &self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        id: &str,
+        handle: Fd,
+        options: HashMap<String, OwnedValue>,
+    ) -> Result<(), MethodError> {
+        let progress = self.state().install_progress.clone();
+        for (status, percentage) in progress {
+            {
+                let mut state = self.state();
+                state.status = status;
+                state.percentage = percentage;
+            }
+
+            let emitted = async {
+                emit_property_changed(connection, "Status", Value::from(status)).await?;
+                emit_property_changed(connection, "Percentage", Value::from(percentage)).await
+            };
+
+            emitted.await.map_err(|_| MethodError::new("Internal", "Install"))?;
+        }
+
+        let mut state = self.call("Install", &[id]).await?;
+        state.installs.push(MockInstall {
+            device_id: id.into(),
+            firmware: read_fd(handle),
+            options,
+        });
+        Ok(())
+    }
+
+    async fn modify_device(&self, id: &str, key: &str, value: &str) -> Result<(), MethodError> {
+        self.call("ModifyDevice", &[id, key, value]).await.map(drop)
+    }
+
+    async fn modify_remote(&self, id: &str, key: &str, value: &str) -> Result<(), MethodError> {
+        self.call("ModifyRemote", &[id, key, value]).await.map(drop)
+    }
+
+    async fn set_approved_firmware(&self, checksums: Vec<String>) -> Result<(), MethodError> {
+        self.call("SetApprovedFirmware", &[]).await?.approved_firmware = checksums;
+        Ok(())
+    }
+
+    async fn set_bios_settings(
+        &self,
+        settings: HashMap<String, String>,
+    ) -> Result<(), MethodError> {
+        let mut args: Vec<String> =
+            settings.iter().map(|(id, value)| [id, "=", value].concat()).collect();
+        args.sort();
+
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        self.call("SetBiosSettings", &args).await.map(drop)
+    }
+
+    async fn set_blocked_firmware(&self, checksums: Vec<String>) -> Result<(), MethodError> {
+        self.call("SetBlockedFirmware", &[]).await?.blocked_firmware = checksums;
+        Ok(())
+    }
+
+    async fn set_feature_flags(&self, flags: u64) -> Result<(), MethodError> {
+        self.call("SetFeatureFlags", &[&flags.to_string()]).await.map(drop)
+    }
+
+    async fn undo_host_security_attr(&self, id: &str) -> Result<(), MethodError> {
+        self.action("UndoHostSecurityAttr", id).await
+    }
+
+    async fn unlock(&self, id: &str) -> Result<(), MethodError> { self.action("Unlock", id).await }
+
+    async fn update_metadata(
+        &self,
+        id: &str,
+        _data: Fd,
+        _signature: Fd,
+    ) -> Result<(), MethodError> {
+        self.action("UpdateMetadata", id).await
+    }
+
+    async fn verify(&self, id: &str) -> Result<(), MethodError> { self.action("Verify", id).await }
+
+    async fn verify_update(&self, id: &str) -> Result<(), MethodError> {
+        self.action("VerifyUpdate", id).await
+    }
+}
+
+/// A scripted fwupd daemon, which is served on a bus until dropped.
+///
+/// Dictionaries given to the daemon are returned to clients as they are, so they may also be
+/// used to feed malformed data to a client.
+pub struct MockDaemon {
+    connection: Connection,
+    state:      Arc<Mutex<State>>,
+}
+
+impl MockDaemon {
+    /// Registers the daemon on the bus, where it is known as `org.freedesktop.fwupd`.
+    pub fn start(bus: &MockBus) -> zbus::Result<Self> {
+        let state = Arc::new(Mutex::new(State {
+            daemon_version: "1.9.0".into(),
+            host_security_id: "HSI:0".into(),
+            ..State::default()
+        }));
+
+        let connection = ConnectionBuilder::address(bus.address())?
+            .name(DBUS_NAME)?
+            .serve_at(DBUS_PATH, Interface { state: state.clone() })?
+            .build()?;
+
+        Ok(Self { connection, state })
+    }
+
+    fn state(&self) -> MutexGuard<State> { self.state.lock().expect("mock daemon state poisoned") }
+
+    /// Adds a device to those returned by `GetDevices`.
+    pub fn add_device(&self, device: DBusDict) { self.state().devices.push(device); }
+
+    /// Adds a downgrade for a device to those returned by `GetDowngrades`.
+    pub fn add_downgrade(&self, device_id: &str, release: DBusDict) {
+        self.state().downgrades.entry(device_id.into()).or_default().push(release);
+    }
+
+    /// Adds a plugin to those returned by `GetPlugins`.
+    pub fn add_plugin(&self, plugin: DBusDict) { self.state().plugins.push(plugin); }
+
+    /// Adds a release for a device to those returned by `GetReleases`.
+    pub fn add_release(&self, device_id: &str, release: DBusDict) {
+        self.state().releases.entry(device_id.into()).or_default().push(release);
+    }
+
+    /// Adds a remote to those returned by `GetRemotes`.
+    pub fn add_remote(&self, remote: DBusDict) { self.state().remotes.push(remote); }
+
+    /// Adds a BIOS setting to those returned by `GetBiosSettings`.
+    pub fn add_bios_setting(&self, setting: DBusDict) { self.state().bios_settings.push(setting); }
+
+    /// Adds a host security attribute to those returned by `GetHostSecurityAttrs`.
+    pub fn add_security_attr(&self, attr: DBusDict) { self.state().security_attrs.push(attr); }
+
+    /// Adds a host security event to those returned by `GetHostSecurityEvents`.
+    pub fn add_security_event(&self, event: DBusDict) { self.state().security_events.push(event); }
+
+    /// Adds an upgrade for a device to those returned by `GetUpgrades`.
+    pub fn add_upgrade(&self, device_id: &str, release: DBusDict) {
+        self.state().upgrades.entry(device_id.into()).or_default().push(release);
+    }
+
+    /// The checksums of the firmware which clients have approved.
+    pub fn approved_firmware(&self) -> Vec<String> { self.state().approved_firmware.clone() }
+
+    /// The checksums of the firmware which clients have blocked.
+    pub fn blocked_firmware(&self) -> Vec<String> { self.state().blocked_firmware.clone() }
+
+    /// The method calls which the daemon has received, in order.
+    pub fn calls(&self) -> Vec<MockCall> { self.state().calls.clone() }
+
+    /// Makes every call of `method`, or read of a property, take at least `delay` to return.
+    pub fn delay(&self, method: &str, delay: Duration) {
+        self.state().delays.insert(method.into(), delay);
+    }
+
+    /// Makes every call of `method` fail with an error of the daemon, such as `NotSupported`.
+    pub fn fail(&self, method: &str, error: &str) {
+        self.state().errors.insert(method.into(), error.into());
+    }
+
+    /// The firmware which clients have installed, in order.
+    pub fn installs(&self) -> Vec<MockInstall> { self.state().installs.clone() }
+
+    /// Sets the `DaemonVersion` property.
+    pub fn set_daemon_version(&self, version: &str) {
+        self.state().daemon_version = version.into();
+    }
+
+    /// Sets the `HostSecurityId` property.
+    pub fn set_host_security_id(&self, id: &str) { self.state().host_security_id = id.into(); }
+
+    /// Sets the statuses and percentages which the daemon reports while it installs firmware.
+    pub fn set_install_progress(&self, progress: &[(u32, u32)]) {
+        self.state().install_progress = progress.to_vec();
+    }
+
+    /// Sets the `Percentage` property, and notifies clients of the change.
+    pub fn set_percentage(&self, percentage: u32) -> zbus::Result<()> {
+        self.state().percentage = percentage;
+        self.emit_property_changed("Percentage", Value::from(percentage))
+    }
+
+    /// Sets the results of the last offline update of a device, returned by `GetResults`.
+    pub fn set_results(&self, device_id: &str, device: DBusDict) {
+        self.state().results.insert(device_id.into(), device);
+    }
+
+    /// Sets the `Status` property, and notifies clients of the change.
+    pub fn set_status(&self, status: u32) -> zbus::Result<()> {
+        self.state().status = status;
+        self.emit_property_changed("Status", Value::from(status))
+    }
+
+    /// Sets the `Tainted` property.
+    pub fn set_tainted(&self, tainted: bool) { self.state().tainted = tainted; }
+
+    /// Emits the `Changed` signal.
+    pub fn emit_changed(&self) -> zbus::Result<()> { self.emit("Changed", &()) }
+
+    /// Emits the `DeviceAdded` signal.
+    pub fn emit_device_added(&self, device: DBusDict) -> zbus::Result<()> {
+        self.emit("DeviceAdded", &(device,))
+    }
+
+    /// Emits the `DeviceChanged` signal.
+    pub fn emit_device_changed(&self, device: DBusDict) -> zbus::Result<()> {
+        self.emit("DeviceChanged", &(device,))
+    }
+
+    /// Emits the `DeviceRemoved` signal.
+    pub fn emit_device_removed(&self, device: DBusDict) -> zbus::Result<()> {
+        self.emit("DeviceRemoved", &(device,))
+    }
+
+    /// Emits the `DeviceRequest` signal.
+    pub fn emit_device_request(&self, request: DBusDict) -> zbus::Result<()> {
+        self.emit("DeviceRequest", &(request,))
+    }
+
+    fn emit<B>(&self, signal: &str, body: &B) -> zbus::Result<()>
+    where
+        B: Serialize + DynamicType,
+    {
+        self.connection.emit_signal(None::<&str>, DBUS_PATH, DBUS_IFACE, signal, body)
+    }
+
+    fn emit_property_changed(&self, property: &str, value: Value) -> zbus::Result<()> {
+        zbus::block_on(emit_property_changed(self.connection.inner(), property, value))
+    }
+}
+
+/// Notifies clients of a change to a property of the daemon.
+async fn emit_property_changed(
+    connection: &zbus::Connection,
+    property: &str,
+    value: Value<'_>,
+) -> zbus::Result<()> {
+    let changed = HashMap::from([(property, value)]);
+    let invalidated: Vec<&str> = Vec::new();
+
+    connection
+        .emit_signal(
+            None::<&str>,
+            DBUS_PATH,
+            "org.freedesktop.DBus.Properties",
+            "PropertiesChanged",
+            &(DBUS_IFACE, changed, invalidated),
+        )
+        .await
+}
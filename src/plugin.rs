@@ -0,0 +1,94 @@
+use crate::{common::*, dbus_helpers::*, DBusDict, Error};
+use std::fmt;
+
+bitflags! {
+    /// Describes the state of a plugin of the daemon.
+    #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+    pub struct PluginFlags: u64 {
+        /// The plugin has been disabled, and will not be used
+        const DISABLED                 = 1;
+        /// The plugin has a warning that should be shown to the user
+        const USER_WARNING             = 1 << 1;
+        /// The updatable flag of devices is cleared when the plugin is disabled
+        const CLEAR_UPDATABLE          = 1 << 2;
+        /// No hardware supported by the plugin was found
+        const NO_HARDWARE              = 1 << 3;
+        /// UEFI capsule updates are not supported by the firmware
+        const CAPSULES_UNSUPPORTED     = 1 << 4;
+        /// The hardware must be unlocked before it can be updated
+        const UNLOCK_REQUIRED          = 1 << 5;
+        /// The efivarfs filesystem is not mounted
+        const EFIVAR_NOT_MOUNTED       = 1 << 6;
+        /// The EFI system partition could not be found
+        const ESP_NOT_FOUND            = 1 << 7;
+        /// The system is running in legacy BIOS mode rather than UEFI mode
+        const LEGACY_BIOS              = 1 << 8;
+        /// The plugin failed to open
+        const FAILED_OPEN              = 1 << 9;
+        /// The plugin requires a hardware ID which the system does not have
+        const REQUIRE_HWID             = 1 << 10;
+        /// The running kernel is too old for the plugin
+        const KERNEL_TOO_OLD           = 1 << 11;
+        /// Authentication is required to use the plugin
+        const AUTH_REQUIRED            = 1 << 12;
+        /// The plugin requires the config file to be secured
+        const SECURE_CONFIG            = 1 << 13;
+        /// The plugin is loaded from an external module
+        const MODULAR                  = 1 << 14;
+        /// The plugin measures the integrity of the system
+        const MEASURE_SYSTEM_INTEGRITY = 1 << 15;
+        /// The plugin has been set up and is ready for use
+        const READY                    = 1 << 16;
+        /// The plugin is only used for testing
+        const TEST_ONLY                = 1 << 17;
+    }
+}
+
+impl Default for PluginFlags {
+    fn default() -> Self { PluginFlags::empty() }
+}
+
+impl fmt::Display for PluginFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { fmt_flags(self, f) }
+}
+
+/// A plugin of the daemon, which provides support for a class of devices.
+#[derive(Clone, Debug, Default)]
+pub struct Plugin {
+    pub flags: PluginFlags,
+    pub name:  Box<str>,
+}
+
+impl Plugin {
+    /// Check if the given `PluginFlags` is set.
+    pub fn has_flag(&self, flags: PluginFlags) -> bool { self.flags.contains(flags) }
+
+    /// Checks if the plugin is disabled, and therefore will not update any devices.
+    pub fn is_disabled(&self) -> bool { self.has_flag(PluginFlags::DISABLED) }
+}
+
+impl TryFrom<DBusDict> for Plugin {
+    type Error = Error;
+
+    fn try_from(dict: DBusDict) -> Result<Self, Error> {
+        let mut plugin = Plugin::default();
+
+        for (key, value) in dict {
+            let key = key.as_str();
+            match key {
+                KEY_FLAGS => plugin.flags = PluginFlags::from_bits_retain(dbus_u64(&value, key)?),
+                KEY_NAME => plugin.name = dbus_str(&value, key)?.into(),
+                other => {
+                    eprintln!(
+                        "unknown plugin key: {} ({}): {:?}",
+                        other,
+                        value.value_signature(),
+                        value
+                    );
+                }
+            }
+        }
+
+        Ok(plugin)
+    }
+}
@@ -1,17 +1,23 @@
-use crate::{common::*, dbus_helpers::*, DBusEntry, RemoteId};
-use dbus::arg::RefArg;
-use std::{cmp::Ordering, iter::FromIterator};
+use crate::{
+    common::*, dbus_helpers::*, version::compare_versions, DBusDict, Device, Error, RemoteId,
+    VersionFormat,
+};
+use std::{cmp::Ordering, collections::BTreeMap, fmt};
 
 bitflags! {
     /// Describes attributes of a release.
+    #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
     pub struct ReleaseFlags: u64 {
-        const TRUSTED_PAYLOAD  = 1;
-        const TRUSTED_METADATA = 1 << 1;
-        const IS_UPGRADE       = 1 << 2;
-        const IS_DOWNGRADE     = 1 << 3;
-        const BLOCKED_VERSION  = 1 << 4;
-        const BLOCKED_APPROVAL = 1 << 5;
-        const UNKNOWN          = std::u64::MAX;
+        const TRUSTED_PAYLOAD     = 1;
+        const TRUSTED_METADATA    = 1 << 1;
+        const IS_UPGRADE          = 1 << 2;
+        const IS_DOWNGRADE        = 1 << 3;
+        const BLOCKED_VERSION     = 1 << 4;
+        const BLOCKED_APPROVAL    = 1 << 5;
+        const IS_ALTERNATE_BRANCH = 1 << 6;
+        const IS_COMMUNITY        = 1 << 7;
+        const TRUSTED_REPORT      = 1 << 8;
+        const UNKNOWN             = std::u64::MAX;
     }
 }
 
@@ -19,8 +25,13 @@ impl Default for ReleaseFlags {
     fn default() -> Self { ReleaseFlags::empty() }
 }
 
+impl fmt::Display for ReleaseFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { fmt_flags(self, f) }
+}
+
 bitflags! {
     /// Describes trust levels for the payload and/or metadata.
+    #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
     pub struct TrustFlags: u64 {
         const PAYLOAD  = 1;
         const METADATA = 1 << 1;
@@ -31,35 +42,151 @@ impl Default for TrustFlags {
     fn default() -> Self { TrustFlags::empty() }
 }
 
+impl fmt::Display for TrustFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { fmt_flags(self, f) }
+}
+
+/// Describes how urgently a release should be installed.
+///
+/// Urgencies are ordered from the least to the most urgent.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Urgency {
+    /// An urgency which is unknown, or too new for this crate, with its raw value.
+    Unknown(u32),
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Default for Urgency {
+    fn default() -> Self { Urgency::Unknown(0) }
+}
+
+impl From<u32> for Urgency {
+    fn from(value: u32) -> Self {
+        use self::Urgency::*;
+        match value {
+            1 => Low,
+            2 => Medium,
+            3 => High,
+            4 => Critical,
+            _ => Unknown(value),
+        }
+    }
+}
+
+impl fmt::Display for Urgency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Urgency::*;
+        let name = match *self {
+            Unknown(value) => return fmt_unknown(value, f),
+            Low => "low",
+            Medium => "medium",
+            High => "high",
+            Critical => "critical",
+        };
+
+        f.write_str(name)
+    }
+}
+
 /// Information about an available fwupd remote.
 #[derive(Clone, Debug, Default, Eq)]
 pub struct Release {
     pub appstream_id:     Box<str>,
+    /// The branch of the firmware, or `None` for the default branch.
+    pub branch:           Option<Box<str>>,
     pub categories:       Box<[Box<str>]>,
     pub checksums:        Box<[Box<str>]>,
     pub created:          u64,
     pub description:      Box<str>,
+    /// Instructions for putting the device into a mode where it can be updated.
+    pub detach_caption:   Option<Box<str>>,
+    /// The URI of an image which illustrates the `detach_caption`.
+    pub detach_image:     Option<Box<str>>,
     pub details_url:      Option<Box<str>>,
     pub filename:         Box<str>,
     pub flags:            ReleaseFlags,
     pub homepage:         Box<str>,
     pub install_duration: u32,
+    /// The security issues which the release fixes, such as `CVE-2022-21894`.
+    pub issues:           Box<[Box<str>]>,
     pub license:          Box<str>,
+    /// The URIs of the mirrors which provide the firmware, in order of preference.
+    pub locations:        Box<[Box<str>]>,
     pub name:             Box<str>,
     pub protocol:         Option<Box<str>>,
     pub remote_id:        RemoteId,
+    /// The URI to send a report of the update to.
+    pub report_uri:       Option<Box<str>>,
     pub size:             u64,
     pub source_url:       Option<Box<str>>,
     pub summary:          Box<str>,
+    pub tags:             Box<[Box<str>]>,
     pub trust_flags:      TrustFlags,
     pub update_message:   Option<Box<str>>,
+    pub urgency:          Urgency,
+    /// The first location of the firmware, which older daemons provide instead of `locations`.
     pub uri:              Box<str>,
     pub vendor:           Box<str>,
     pub version:          Box<str>,
 }
 
+impl Release {
+    /// The locations of the firmware, including the `uri` of older daemons.
+    pub fn uris(&self) -> impl Iterator<Item = &str> {
+        let uri = Some(&*self.uri)
+            .filter(|uri| !uri.is_empty() && !self.locations.iter().any(|l| &**l == *uri));
+
+        self.locations.iter().map(AsRef::as_ref).chain(uri)
+    }
+
+    /// The CVE IDs among the issues which the release fixes.
+    pub fn cves(&self) -> impl Iterator<Item = &str> {
+        self.issues.iter().map(AsRef::as_ref).filter(|issue: &&str| {
+            issue.get(..4).map_or(false, |prefix| prefix.eq_ignore_ascii_case("CVE-"))
+        })
+    }
+
+    /// Checks if the release fixes the given CVE, such as `CVE-2022-21894`.
+    pub fn fixes_cve(&self, cve: &str) -> bool { self.cves().any(|c| c.eq_ignore_ascii_case(cve)) }
+
+    /// Checks if the release fixes any security issues.
+    pub fn is_security_update(&self) -> bool { !self.issues.is_empty() }
+}
+
+/// Sorts releases from the most to the least urgent, and then from the newest to the oldest.
+pub fn sort_by_urgency(releases: &mut [Release]) {
+    releases.sort_by(|a, b| b.urgency.cmp(&a.urgency).then_with(|| b.cmp(a)));
+}
+
+/// The releases of a device grouped by their branch, with `None` for the default branch.
+pub type Branches = BTreeMap<Option<Box<str>>, Vec<Release>>;
+
+/// The name which the daemon gives to the default branch.
+pub(crate) const DEFAULT_BRANCH: &str = "default";
+
+/// Groups releases by their branch, ordering the releases of each branch from newest to oldest.
+pub(crate) fn group_by_branch(device: &Device, releases: Vec<Release>) -> Branches {
+    let format = device.version_format.unwrap_or_default();
+
+    let mut branches = Branches::new();
+    for release in releases {
+        branches.entry(release.branch.clone()).or_default().push(release);
+    }
+
+    for releases in branches.values_mut() {
+        releases.sort_by(|a, b| compare_versions(&b.version, &a.version, format));
+    }
+
+    branches
+}
+
 impl Ord for Release {
-    fn cmp(&self, other: &Self) -> Ordering { self.version.cmp(&other.version) }
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_versions(&self.version, &other.version, VersionFormat::default())
+    }
 }
 
 impl PartialOrd for Release {
@@ -67,78 +194,62 @@ impl PartialOrd for Release {
 }
 
 impl PartialEq for Release {
-    fn eq(&self, other: &Self) -> bool { self.version == other.version }
+    fn eq(&self, other: &Self) -> bool { self.cmp(other) == Ordering::Equal }
 }
 
 impl AsRef<RemoteId> for Release {
     fn as_ref(&self) -> &RemoteId { &self.remote_id }
 }
 
-impl FromIterator<DBusEntry> for Release {
-    fn from_iter<T>(iter: T) -> Self
-    where
-        T: IntoIterator<Item = DBusEntry>,
-    {
+impl TryFrom<DBusDict> for Release {
+    type Error = Error;
+
+    fn try_from(dict: DBusDict) -> Result<Self, Error> {
         let mut release = Release::default();
 
-        for (key, value) in iter {
+        for (key, value) in dict {
             let key = key.as_str();
             match key {
-                KEY_APPSTREAM_ID => release.appstream_id = dbus_str(&value, key).into(),
-                KEY_CATEGORIES => {
-                    release.categories = value
-                        .as_iter()
-                        .expect("Categories is not a variant")
-                        .flat_map(|array| array.as_iter().expect("Categories is not an iterator"))
-                        .map(|value| dbus_str(&value, key).into())
-                        .collect::<Vec<Box<str>>>()
-                        .into_boxed_slice()
-                }
+                KEY_APPSTREAM_ID => release.appstream_id = dbus_str(&value, key)?.into(),
+                KEY_BRANCH => release.branch = Some(dbus_str(&value, key)?.into()),
+                KEY_CATEGORIES => release.categories = dbus_str_array(&value, key)?,
                 KEY_CHECKSUM => {
-                    release.checksums = value
-                        .as_iter()
-                        .expect("Checksums is not a variant")
-                        .map(|value| dbus_str(&value, key).into())
-                        .flat_map(|value: Box<str>| {
-                            if value.contains(',') {
-                                value.split(',').map(|x| x.into()).collect()
-                            } else {
-                                vec![value]
-                            }
-                        })
-                        .collect::<Vec<Box<str>>>()
-                        .into_boxed_slice()
-                }
-                KEY_CREATED => release.created = dbus_u64(&value, key),
-                KEY_DESCRIPTION => release.description = dbus_str(&value, key).into(),
-                KEY_DETAILS_URL => release.details_url = Some(dbus_str(&value, key).into()),
-                KEY_FILENAME => release.filename = dbus_str(&value, key).into(),
-                KEY_FLAGS => {
-                    release.flags = ReleaseFlags::from_bits_truncate(dbus_u64(&value, key))
+                    release.checksums = dbus_str(&value, key)?.split(',').map(Box::from).collect()
                 }
-                KEY_HOMEPAGE => release.homepage = dbus_str(&value, key).into(),
-                KEY_INSTALL_DURATION => release.install_duration = dbus_u64(&value, key) as u32,
-                KEY_LICENSE => release.license = dbus_str(&value, key).into(),
-                // KEY_METADATA => (),
-                KEY_NAME => release.name = dbus_str(&value, key).into(),
-                KEY_PROTOCOL => release.protocol = Some(dbus_str(&value, key).into()),
-                KEY_REMOTE_ID => release.remote_id = RemoteId(dbus_str(&value, key).into()),
-                KEY_SIZE => release.size = dbus_u64(&value, key),
-                KEY_SOURCE_URL => release.source_url = Some(dbus_str(&value, key).into()),
-                KEY_SUMMARY => release.summary = dbus_str(&value, key).into(),
+                KEY_CREATED => release.created = dbus_u64(&value, key)?,
+                KEY_DESCRIPTION => release.description = dbus_str(&value, key)?.into(),
+                KEY_DETACH_CAPTION => release.detach_caption = Some(dbus_str(&value, key)?.into()),
+                KEY_DETACH_IMAGE => release.detach_image = Some(dbus_str(&value, key)?.into()),
+                KEY_DETAILS_URL => release.details_url = Some(dbus_str(&value, key)?.into()),
+                KEY_FILENAME => release.filename = dbus_str(&value, key)?.into(),
+                KEY_FLAGS => release.flags = ReleaseFlags::from_bits_retain(dbus_u64(&value, key)?),
+                KEY_HOMEPAGE => release.homepage = dbus_str(&value, key)?.into(),
+                KEY_INSTALL_DURATION => release.install_duration = dbus_u64(&value, key)? as u32,
+                KEY_ISSUES => release.issues = dbus_str_array(&value, key)?,
+                KEY_LICENSE => release.license = dbus_str(&value, key)?.into(),
+                KEY_LOCATIONS => release.locations = dbus_str_array(&value, key)?,
+                KEY_NAME => release.name = dbus_str(&value, key)?.into(),
+                KEY_PROTOCOL => release.protocol = Some(dbus_str(&value, key)?.into()),
+                KEY_REMOTE_ID => release.remote_id = RemoteId(dbus_str(&value, key)?.into()),
+                KEY_REPORT_URI => release.report_uri = Some(dbus_str(&value, key)?.into()),
+                KEY_SIZE => release.size = dbus_u64(&value, key)?,
+                KEY_SOURCE_URL => release.source_url = Some(dbus_str(&value, key)?.into()),
+                KEY_SUMMARY => release.summary = dbus_str(&value, key)?.into(),
+                KEY_TAGS => release.tags = dbus_str_array(&value, key)?,
                 KEY_TRUST_FLAGS => {
-                    release.trust_flags = TrustFlags::from_bits_truncate(dbus_u64(&value, key))
+                    release.trust_flags = TrustFlags::from_bits_retain(dbus_u64(&value, key)?)
                 }
-                KEY_UPDATE_MESSAGE => release.update_message = Some(dbus_str(&value, key).into()),
-                KEY_URI => release.uri = dbus_str(&value, key).into(),
-                KEY_VENDOR => release.vendor = dbus_str(&value, key).into(),
-                KEY_VERSION => release.version = dbus_str(&value, key).into(),
+                KEY_UPDATE_MESSAGE => release.update_message = Some(dbus_str(&value, key)?.into()),
+                KEY_URGENCY => release.urgency = Urgency::from(dbus_u64(&value, key)? as u32),
+                KEY_URI => release.uri = dbus_str(&value, key)?.into(),
+                KEY_VENDOR => release.vendor = dbus_str(&value, key)?.into(),
+                KEY_VERSION => release.version = dbus_str(&value, key)?.into(),
                 other => {
-                    eprintln!("unknown release key: {} ({})", other, value.signature());
+                    eprintln!("unknown release key: {} ({})", other, value.value_signature());
                 }
             }
         }
 
-        release
+        Ok(release)
     }
 }
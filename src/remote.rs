@@ -1,71 +1,102 @@
-use crate::{common::*, dbus_helpers::*, Client, DBusEntry};
-use dbus::arg::RefArg;
+use crate::{
+    common::*, dbus_helpers::*, download::copy_download, jcat, Cancellable, Client, DBusDict,
+    DownloadError, DownloadRequest, Downloader, Error, JcatError, Release,
+};
 use std::{
     borrow::Cow,
-    fs::{metadata, File, OpenOptions},
+    fmt,
+    fs::{self, metadata, File, OpenOptions},
     io::{self, Seek, SeekFrom},
-    iter::FromIterator,
     path::{Path, PathBuf},
     time::{Duration, SystemTime},
 };
 use url::Url;
 
 /// Describes the type of keyring to use with a remote.
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
 pub enum KeyringKind {
-    Unknown,
+    /// A keyring which is unknown, or too new for this crate, with its raw value.
+    Unknown(u32),
+    #[default]
     None,
     GPG,
     PKCS7,
     JCAT,
 }
 
-impl From<u8> for KeyringKind {
-    fn from(value: u8) -> KeyringKind {
+impl From<u32> for KeyringKind {
+    fn from(value: u32) -> KeyringKind {
         use self::KeyringKind::*;
         match value {
-            0 => Unknown,
             1 => None,
             2 => GPG,
             3 => PKCS7,
             4 => JCAT,
-            _ => Unknown,
+            _ => Unknown(value),
         }
     }
 }
 
-impl Default for KeyringKind {
-    fn default() -> Self { KeyringKind::None }
+impl fmt::Display for KeyringKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::KeyringKind::*;
+        let name = match *self {
+            Unknown(value) => return fmt_unknown(value, f),
+            None => "none",
+            GPG => "gpg",
+            PKCS7 => "pkcs7",
+            JCAT => "jcat",
+        };
+
+        f.write_str(name)
+    }
 }
 
 /// Describes the kind of remote.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum RemoteKind {
-    Unknown,
+    /// A kind which is unknown, or too new for this crate, with its raw value.
+    Unknown(u32),
     Download,
     Local,
     Directory,
 }
 
-impl From<u8> for RemoteKind {
-    fn from(value: u8) -> RemoteKind {
+impl Default for RemoteKind {
+    fn default() -> Self { RemoteKind::Unknown(0) }
+}
+
+impl From<u32> for RemoteKind {
+    fn from(value: u32) -> RemoteKind {
         use self::RemoteKind::*;
         match value {
             1 => Download,
             2 => Local,
             3 => Directory,
-            _ => Unknown,
+            _ => Unknown(value),
         }
     }
 }
 
-impl Default for RemoteKind {
-    fn default() -> Self { RemoteKind::Unknown }
+impl fmt::Display for RemoteKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::RemoteKind::*;
+        let name = match *self {
+            Unknown(value) => return fmt_unknown(value, f),
+            Download => "download",
+            Local => "local",
+            Directory => "directory",
+        };
+
+        f.write_str(name)
+    }
 }
 
 /// An error that may occur when updating the metadata for a remote.
 #[derive(Debug, Error)]
 pub enum UpdateError {
+    #[error("the update of the metadata was cancelled")]
+    Cancelled,
     #[error("fwupd client errored when updating metadata for remote")]
     Client(#[source] crate::Error),
     #[error("failed to write firmware metadata to disk")]
@@ -73,7 +104,7 @@ pub enum UpdateError {
     #[error("failed to create parent directories for the remote's metadata cache")]
     CreateParent(#[source] io::Error),
     #[error("remote returned error when fetching firmware metadata")]
-    Get(#[source] ureq::Error),
+    Get(#[source] DownloadError),
     #[error("attempted to update a remote without a URI")]
     NoUri,
     #[error("unable to open cached firmware metadata ({:?}) for remote", _1)]
@@ -86,6 +117,8 @@ pub enum UpdateError {
     Truncate(#[source] io::Error),
     #[error("failed to get fwupd user agent")]
     UserAgent(#[source] crate::Error),
+    #[error("downloaded metadata does not match its JCAT file")]
+    Verify(#[source] JcatError),
 }
 
 /// The remote ID of a remote.
@@ -116,48 +149,78 @@ pub struct Remote {
 
 impl Remote {
     /// Updates the metadata for this remote.
-    pub fn update_metadata(&self, client: &Client) -> Result<(), UpdateError> {
+    ///
+    /// If the `cancellable` is cancelled, the download stops and its file is removed.
+    pub fn update_metadata(
+        &self,
+        client: &Client,
+        cancellable: Option<&Cancellable>,
+    ) -> Result<(), UpdateError> {
         if !self.enabled {
             return Ok(());
         }
 
         if let Some(ref uri) = self.uri {
-            if let Some(file) = self.update_file(&client.http, uri)? {
-                let sig = self.update_signature(&client.http, uri)?;
-                client.update_metadata(&self, file, sig).map_err(UpdateError::Client)?;
+            let downloader = &*client.downloader;
+            if let Some(mut file) = self.update_file(downloader, uri, cancellable)? {
+                let mut sig = self.update_signature(downloader, uri, cancellable)?;
+
+                if client.verify_metadata && self.keyring == KeyringKind::JCAT {
+                    let id = Path::new(uri.as_ref()).file_name().and_then(|name| name.to_str());
+                    if let Err(why) = jcat::verify(&mut sig, id.unwrap_or_default(), &mut file) {
+                        // Fetch the metadata again next time, rather than trusting the cache.
+                        let _ = fs::remove_file(self.local_cache(self.filename_cache.as_ref()));
+                        return Err(UpdateError::Verify(why));
+                    }
+
+                    sig.seek(SeekFrom::Start(0)).map_err(UpdateError::Seek)?;
+                }
+
+                client.update_metadata(self, file, sig).map_err(UpdateError::Client)?;
             }
         }
 
         Ok(())
     }
 
-    pub(crate) fn firmware_uri(&self, url: &str) -> Url {
-        let uri = if let Some(ref firmware_base_uri) = self.firmware_base_uri {
-            let mut firmware_base_uri: &str = firmware_base_uri;
-            if firmware_base_uri.ends_with('/') {
-                firmware_base_uri = &firmware_base_uri[..firmware_base_uri.len() - 1];
+    /// The URIs to download the firmware of a release from, in the order to try them.
+    ///
+    /// The `firmware_base_uri` of the remote takes precedence over the locations of the release.
+    /// Locations which do not form a valid URI are skipped.
+    pub(crate) fn firmware_uris(&self, release: &Release) -> Vec<Url> {
+        let (mut rebased, mut raw) = (Vec::new(), Vec::new());
+        for location in release.uris() {
+            let rebased_uri = self.firmware_uri(location);
+            let raw_uri = location.parse::<Url>().ok();
+            if rebased_uri.is_none() && raw_uri.is_none() {
+                info!("skipping invalid firmware location {} of {}", location, &*self.remote_id);
+            }
+
+            rebased.extend(rebased_uri);
+            raw.extend(raw_uri);
+        }
+
+        let mut uris = Vec::new();
+        for uri in rebased.into_iter().chain(raw) {
+            if !uris.contains(&uri) {
+                uris.push(uri);
             }
+        }
 
-            let basename = Path::new(url)
-                .file_name()
-                .expect("release URI without basename")
-                .to_str()
-                .expect("basename of release URI is not UTF-8");
+        uris
+    }
+
+    /// The URI of the firmware at `url` on this remote, if it forms a valid URI.
+    pub(crate) fn firmware_uri(&self, url: &str) -> Option<Url> {
+        let uri = if let Some(ref firmware_base_uri) = self.firmware_base_uri {
+            let firmware_base_uri = firmware_base_uri.trim_end_matches('/');
+            let basename = Path::new(url).file_name()?.to_str()?;
 
             Cow::Owned([firmware_base_uri, "/", basename].concat())
         // Use the base URI of the metadata to build the full path.
         } else if !url.contains('/') {
-            let remote_uri: &str = self.uri.as_ref().expect("remote URI without URI");
-            let mut dirname = Path::new(remote_uri)
-                .parent()
-                .expect("metadata URI without parent")
-                .as_os_str()
-                .to_str()
-                .expect("metadata URI is not UTF-8");
-
-            if dirname.ends_with('/') {
-                dirname = &dirname[..dirname.len() - 1];
-            }
+            let remote_uri: &str = self.uri.as_deref()?;
+            let dirname = Path::new(remote_uri).parent()?.to_str()?.trim_end_matches('/');
 
             Cow::Owned([dirname, "/", url].concat())
         // A normal URI
@@ -165,12 +228,12 @@ impl Remote {
             Cow::Borrowed(url)
         };
 
-        uri.parse::<Url>().expect("firmware uri is not a valid uri")
+        uri.parse::<Url>().ok()
     }
 
     /// Fetch the time since the last update, if such a time can be fetched.
     pub fn time_since_last_update(&self) -> Option<Duration> {
-        metadata(&self.local_cache(self.filename_cache.as_ref()))
+        metadata(self.local_cache(self.filename_cache.as_ref()))
             .and_then(|md| md.modified())
             .ok()
             .and_then(|modified| SystemTime::now().duration_since(modified).ok())
@@ -180,12 +243,17 @@ impl Remote {
         let file_name =
             Path::new(file).file_name().expect("remote filename cache does not have a file name");
 
-        let id: &str = &*self.remote_id;
+        let id: &str = &self.remote_id;
         cache_path(&Path::new(id).join(file_name))
     }
 
     /// Fetch the latest firmware from the remote
-    fn update_file(&self, http: &ureq::Agent, uri: &str) -> Result<Option<File>, UpdateError> {
+    fn update_file(
+        &self,
+        downloader: &dyn Downloader,
+        uri: &str,
+        cancellable: Option<&Cancellable>,
+    ) -> Result<Option<File>, UpdateError> {
         let local_cache = &self.local_cache(self.filename_cache.as_ref());
         let checksum = self.checksum.as_ref().unwrap();
 
@@ -201,13 +269,18 @@ impl Remote {
             }
         };
 
-        let file = Remote::fetch(http, uri, local_cache)?;
+        let file = Remote::fetch(downloader, uri, local_cache, cancellable)?;
 
         Ok(Some(file))
     }
 
     /// Fetch the latest signature for the remote
-    fn update_signature(&self, http: &ureq::Agent, uri: &str) -> Result<File, UpdateError> {
+    fn update_signature(
+        &self,
+        downloader: &dyn Downloader,
+        uri: &str,
+        cancellable: Option<&Cancellable>,
+    ) -> Result<File, UpdateError> {
         let extension = match self.keyring {
             KeyringKind::JCAT => ".jcat",
             KeyringKind::PKCS7 => ".p7b",
@@ -217,15 +290,20 @@ impl Remote {
         let cache = &self.local_cache(&[self.filename_cache.as_ref(), extension].concat());
         let uri = [uri, extension].concat();
 
-        Remote::fetch(http, &uri, cache)
+        Remote::fetch(downloader, &uri, cache, cancellable)
     }
 
     /// Fetch a file from a remote URI to disk
-    fn fetch(http: &ureq::Agent, uri: &str, file: &Path) -> Result<File, UpdateError> {
-        info!("fetching {} to {:?}", uri, file);
-
-        if file.exists() {
-            let _ = std::fs::remove_file(file);
+    fn fetch(
+        downloader: &dyn Downloader,
+        uri: &str,
+        path: &Path,
+        cancellable: Option<&Cancellable>,
+    ) -> Result<File, UpdateError> {
+        info!("fetching {} to {:?}", uri, path);
+
+        if path.exists() {
+            let _ = std::fs::remove_file(path);
         }
 
         // Open the file that we're going to write to
@@ -233,13 +311,17 @@ impl Remote {
             .read(true)
             .write(true)
             .create(true)
-            .open(file)
-            .map_err(|why| UpdateError::Open(why, file.to_path_buf()))?;
+            .open(path)
+            .map_err(|why| UpdateError::Open(why, path.to_path_buf()))?;
 
         // Initiate connection to fetch firmware from remote
-        let mut resp = http.get(uri).call().map_err(UpdateError::Get)?.into_reader();
+        let mut resp =
+            downloader.download(&DownloadRequest::new(uri)).map_err(UpdateError::Get)?.body;
 
-        std::io::copy(&mut resp, &mut file).map_err(UpdateError::Copy)?;
+        if !copy_download(&mut *resp, &mut file, cancellable, |_| ()).map_err(UpdateError::Copy)? {
+            let _ = std::fs::remove_file(path);
+            return Err(UpdateError::Cancelled);
+        }
 
         file.seek(SeekFrom::Start(0)).map_err(UpdateError::Seek)?;
 
@@ -251,47 +333,43 @@ impl AsRef<RemoteId> for Remote {
     fn as_ref(&self) -> &RemoteId { &self.remote_id }
 }
 
-impl FromIterator<DBusEntry> for Remote {
-    fn from_iter<T>(iter: T) -> Self
-    where
-        T: IntoIterator<Item = DBusEntry>,
-    {
+impl TryFrom<DBusDict> for Remote {
+    type Error = Error;
+
+    fn try_from(dict: DBusDict) -> Result<Self, Error> {
         let mut remote = Remote::default();
 
-        for (key, value) in iter {
+        for (key, value) in dict {
             let key = key.as_str();
             match key {
-                "Agreement" => remote.agreement = Some(dbus_str(&value, key).into()),
-                "ApprovalRequired" => remote.approval_required = dbus_u64(&value, key) != 0,
-                KEY_CHECKSUM => remote.checksum = Some(dbus_str(&value, key).into()),
-                "Enabled" => remote.enabled = dbus_u64(&value, key) != 0,
-                "FilenameCache" => remote.filename_cache = dbus_str(&value, key).into(),
-                "FilenameSource" => remote.filename_source = dbus_str(&value, key).into(),
-                "FirmwareBaseUri" => remote.firmware_base_uri = Some(dbus_str(&value, key).into()),
-                "Keyring" => remote.keyring = KeyringKind::from(dbus_u64(&value, key) as u8),
-                "ModificationTime" => remote.modification_time = dbus_u64(&value, key),
-                "Password" => remote.password = Some(dbus_str(&value, key).into()),
-                "Priority" => {
-                    let value = value
-                        .as_iter()
-                        .expect("Priority is not a variant")
-                        .next()
-                        .expect("Priority does not contain a value");
-
-                    remote.priority = dbus_i64(&value, key) as i16;
-                }
-                KEY_REMOTE_ID => remote.remote_id = RemoteId(dbus_str(&value, key).into()),
-                "ReportUri" => remote.report_uri = Some(dbus_str(&value, key).into()),
-                "Title" => remote.title = dbus_str(&value, key).into(),
-                "Type" => remote.kind = RemoteKind::from(dbus_u64(&value, key) as u8),
-                "Username" => remote.username = Some(dbus_str(&value, key).into()),
-                KEY_URI => remote.uri = Some(dbus_str(&value, key).into()),
+                "Agreement" => remote.agreement = Some(dbus_str(&value, key)?.into()),
+                "ApprovalRequired" => remote.approval_required = dbus_u64(&value, key)? != 0,
+                KEY_CHECKSUM => remote.checksum = Some(dbus_str(&value, key)?.into()),
+                "Enabled" => remote.enabled = dbus_u64(&value, key)? != 0,
+                "FilenameCache" => remote.filename_cache = dbus_str(&value, key)?.into(),
+                "FilenameSource" => remote.filename_source = dbus_str(&value, key)?.into(),
+                "FirmwareBaseUri" => remote.firmware_base_uri = Some(dbus_str(&value, key)?.into()),
+                "Keyring" => remote.keyring = KeyringKind::from(dbus_u64(&value, key)? as u32),
+                "ModificationTime" => remote.modification_time = dbus_u64(&value, key)?,
+                "Password" => remote.password = Some(dbus_str(&value, key)?.into()),
+                "Priority" => remote.priority = dbus_i64(&value, key)? as i16,
+                KEY_REMOTE_ID => remote.remote_id = RemoteId(dbus_str(&value, key)?.into()),
+                "ReportUri" => remote.report_uri = Some(dbus_str(&value, key)?.into()),
+                "Title" => remote.title = dbus_str(&value, key)?.into(),
+                "Type" => remote.kind = RemoteKind::from(dbus_u64(&value, key)? as u32),
+                "Username" => remote.username = Some(dbus_str(&value, key)?.into()),
+                KEY_URI => remote.uri = Some(dbus_str(&value, key)?.into()),
                 other => {
-                    eprintln!("unknown remote key: {} ({}): {:?}", other, value.signature(), value);
+                    eprintln!(
+                        "unknown remote key: {} ({}): {:?}",
+                        other,
+                        value.value_signature(),
+                        value
+                    );
                 }
             }
         }
 
-        remote
+        Ok(remote)
     }
 }
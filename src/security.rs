@@ -0,0 +1,279 @@
+use crate::{common::*, dbus_helpers::*, DBusDict, Error};
+use std::fmt;
+
+bitflags! {
+    /// Describes attributes of a host security attribute.
+    #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+    pub struct SecurityAttrFlags: u64 {
+        /// The attribute has been checked and is in a secure state
+        const SUCCESS             = 1;
+        /// The attribute has been superseded by a different attribute
+        const OBSOLETED           = 1 << 1;
+        /// The attribute is missing data required to check it
+        const MISSING_DATA        = 1 << 2;
+        /// The attribute affects the runtime updates of the system
+        const RUNTIME_UPDATES     = 1 << 8;
+        /// The attribute affects the runtime attestation of the system
+        const RUNTIME_ATTESTATION = 1 << 9;
+        /// The attribute describes a runtime issue, e.g. a tainted kernel
+        const RUNTIME_ISSUE       = 1 << 10;
+        /// Fixing the attribute requires contacting the OEM
+        const ACTION_CONTACT_OEM  = 1 << 11;
+        /// Fixing the attribute requires changing the firmware configuration
+        const ACTION_CONFIG_FW    = 1 << 12;
+        /// Fixing the attribute requires changing the operating system configuration
+        const ACTION_CONFIG_OS    = 1 << 13;
+        /// The attribute can be fixed by the daemon
+        const CAN_FIX             = 1 << 14;
+        /// A fix of the attribute can be undone by the daemon
+        const CAN_UNDO            = 1 << 15;
+    }
+}
+
+impl Default for SecurityAttrFlags {
+    fn default() -> Self { SecurityAttrFlags::empty() }
+}
+
+impl fmt::Display for SecurityAttrFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { fmt_flags(self, f) }
+}
+
+/// The highest HSI level which the daemon assigns to an attribute.
+const HSI_LEVEL_LAST: u32 = 5;
+
+/// Describes the result of a host security check.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HsiResult {
+    /// A result which is unknown, or too new for this crate, with its raw value.
+    Unknown(u32),
+    Enabled,
+    NotEnabled,
+    Valid,
+    NotValid,
+    Locked,
+    NotLocked,
+    Encrypted,
+    NotEncrypted,
+    Tainted,
+    NotTainted,
+    Found,
+    NotFound,
+    Supported,
+    NotSupported,
+}
+
+impl Default for HsiResult {
+    fn default() -> Self { HsiResult::Unknown(0) }
+}
+
+impl From<u32> for HsiResult {
+    fn from(value: u32) -> Self {
+        use self::HsiResult::*;
+        match value {
+            1 => Enabled,
+            2 => NotEnabled,
+            3 => Valid,
+            4 => NotValid,
+            5 => Locked,
+            6 => NotLocked,
+            7 => Encrypted,
+            8 => NotEncrypted,
+            9 => Tainted,
+            10 => NotTainted,
+            11 => Found,
+            12 => NotFound,
+            13 => Supported,
+            14 => NotSupported,
+            _ => Unknown(value),
+        }
+    }
+}
+
+impl fmt::Display for HsiResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::HsiResult::*;
+        let name = match *self {
+            Unknown(value) => return fmt_unknown(value, f),
+            Enabled => "enabled",
+            NotEnabled => "not-enabled",
+            Valid => "valid",
+            NotValid => "not-valid",
+            Locked => "locked",
+            NotLocked => "not-locked",
+            Encrypted => "encrypted",
+            NotEncrypted => "not-encrypted",
+            Tainted => "tainted",
+            NotTainted => "not-tainted",
+            Found => "found",
+            NotFound => "not-found",
+            Supported => "supported",
+            NotSupported => "not-supported",
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// A host security attribute, which contributes to the Host Security ID of the system.
+#[derive(Clone, Debug, Default)]
+pub struct SecurityAttr {
+    pub appstream_id:               Box<str>,
+    pub bios_setting_current_value: Option<Box<str>>,
+    pub bios_setting_id:            Option<Box<str>>,
+    pub bios_setting_target_value:  Option<Box<str>>,
+    pub created:                    u64,
+    pub description:                Option<Box<str>>,
+    pub flags:                      SecurityAttrFlags,
+    pub guid:                       Box<[Box<str>]>,
+    pub hsi_level:                  u32,
+    pub kernel_current_value:       Option<Box<str>>,
+    pub kernel_target_value:        Option<Box<str>>,
+    pub name:                       Box<str>,
+    pub plugin:                     Box<str>,
+    pub result:                     HsiResult,
+    pub result_fallback:            Option<HsiResult>,
+    pub result_success:             Option<HsiResult>,
+    pub summary:                    Option<Box<str>>,
+    pub title:                      Option<Box<str>>,
+    pub url:                        Option<Box<str>>,
+}
+
+impl SecurityAttr {
+    /// Check if the given `SecurityAttrFlags` is set.
+    pub fn has_flag(&self, flags: SecurityAttrFlags) -> bool { self.flags.contains(flags) }
+
+    /// Checks if the attribute has been superseded by another attribute.
+    pub fn is_obsoleted(&self) -> bool { self.has_flag(SecurityAttrFlags::OBSOLETED) }
+
+    /// Checks if the attribute is in a secure state.
+    pub fn is_success(&self) -> bool { self.has_flag(SecurityAttrFlags::SUCCESS) }
+}
+
+impl TryFrom<DBusDict> for SecurityAttr {
+    type Error = Error;
+
+    fn try_from(dict: DBusDict) -> Result<Self, Error> {
+        let mut attr = SecurityAttr::default();
+
+        for (key, value) in dict {
+            let key = key.as_str();
+            match key {
+                KEY_APPSTREAM_ID => attr.appstream_id = dbus_str(&value, key)?.into(),
+                "BiosSettingCurrentValue" => {
+                    attr.bios_setting_current_value = Some(dbus_str(&value, key)?.into())
+                }
+                "BiosSettingId" => attr.bios_setting_id = Some(dbus_str(&value, key)?.into()),
+                "BiosSettingTargetValue" => {
+                    attr.bios_setting_target_value = Some(dbus_str(&value, key)?.into())
+                }
+                KEY_CREATED => attr.created = dbus_u64(&value, key)?,
+                KEY_DESCRIPTION => attr.description = Some(dbus_str(&value, key)?.into()),
+                KEY_FLAGS => {
+                    attr.flags = SecurityAttrFlags::from_bits_retain(dbus_u64(&value, key)?)
+                }
+                KEY_GUID => attr.guid = dbus_str_array(&value, key)?,
+                "HsiLevel" => attr.hsi_level = dbus_u64(&value, key)? as u32,
+                "HsiResult" => attr.result = HsiResult::from(dbus_u64(&value, key)? as u32),
+                "HsiResultFallback" => {
+                    attr.result_fallback = Some(HsiResult::from(dbus_u64(&value, key)? as u32))
+                }
+                "HsiResultSuccess" => {
+                    attr.result_success = Some(HsiResult::from(dbus_u64(&value, key)? as u32))
+                }
+                "KernelCurrentValue" => {
+                    attr.kernel_current_value = Some(dbus_str(&value, key)?.into())
+                }
+                "KernelTargetValue" => {
+                    attr.kernel_target_value = Some(dbus_str(&value, key)?.into())
+                }
+                KEY_METADATA => (),
+                KEY_NAME => attr.name = dbus_str(&value, key)?.into(),
+                KEY_PLUGIN => attr.plugin = dbus_str(&value, key)?.into(),
+                KEY_SUMMARY => attr.summary = Some(dbus_str(&value, key)?.into()),
+                "Title" => attr.title = Some(dbus_str(&value, key)?.into()),
+                KEY_URI => attr.url = Some(dbus_str(&value, key)?.into()),
+                other => {
+                    eprintln!(
+                        "unknown security attribute key: {} ({}): {:?}",
+                        other,
+                        value.value_signature(),
+                        value
+                    );
+                }
+            }
+        }
+
+        Ok(attr)
+    }
+}
+
+/// A change to a host security attribute, as recorded in the history of the daemon.
+#[derive(Clone, Debug, Default)]
+pub struct SecurityEvent {
+    /// The attribute after the change, with the time of the change as its creation time.
+    pub attr: SecurityAttr,
+}
+
+impl SecurityEvent {
+    /// The time at which the change was recorded.
+    pub fn created(&self) -> u64 { self.attr.created }
+
+    /// The result of the attribute after the change.
+    pub fn current(&self) -> HsiResult { self.attr.result }
+
+    /// Checks if the change left the attribute in an insecure state.
+    pub fn is_regression(&self) -> bool {
+        self.previous() != self.current() && !self.attr.is_success()
+    }
+
+    /// The result of the attribute before the change.
+    pub fn previous(&self) -> HsiResult { self.attr.result_fallback.unwrap_or_default() }
+}
+
+impl TryFrom<DBusDict> for SecurityEvent {
+    type Error = Error;
+
+    fn try_from(dict: DBusDict) -> Result<Self, Error> {
+        SecurityAttr::try_from(dict).map(|attr| SecurityEvent { attr })
+    }
+}
+
+/// Calculates the HSI level of the host from its security attributes.
+///
+/// This is the highest level at which no attribute failed, and at least one succeeded, as
+/// calculated by the daemon.
+pub fn hsi_level(attrs: &[SecurityAttr]) -> u32 {
+    let mut level = 0;
+
+    for current in 1..=HSI_LEVEL_LAST {
+        let attrs = attrs.iter().filter(|attr| attr.hsi_level == current);
+
+        let mut succeeded = false;
+        for attr in attrs {
+            if attr.is_success() {
+                succeeded = true;
+            } else if !attr.is_obsoleted() {
+                return current - 1;
+            }
+        }
+
+        if succeeded {
+            level = current;
+        }
+    }
+
+    level
+}
+
+/// Computes the HSI summary of the host from its security attributes, e.g. `HSI:2!`.
+///
+/// A trailing `!` denotes that a runtime issue, such as a tainted kernel, was found.
+pub fn hsi_summary(attrs: &[SecurityAttr]) -> String {
+    let runtime_issue = attrs.iter().any(|attr| {
+        !attr.is_obsoleted()
+            && !attr.is_success()
+            && attr.has_flag(SecurityAttrFlags::RUNTIME_ISSUE)
+    });
+
+    format!("HSI:{}{}", hsi_level(attrs), if runtime_issue { "!" } else { "" })
+}
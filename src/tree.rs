@@ -0,0 +1,201 @@
+use crate::{Device, DeviceFlags, DeviceId};
+use std::collections::HashMap;
+
+/// The devices of the daemon, arranged by their `parent_device_id`.
+///
+/// Devices whose parent is not known to the daemon are treated as roots. A root and all of its
+/// descendants form a composite device, such as a dock with its hub and PD controller.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceTree {
+    devices:  Vec<Device>,
+    children: Vec<Vec<usize>>,
+    parents:  Vec<Option<usize>>,
+    roots:    Vec<usize>,
+    indices:  HashMap<DeviceId, usize>,
+}
+
+impl DeviceTree {
+    pub fn new(devices: Vec<Device>) -> Self {
+        let indices: HashMap<DeviceId, usize> = devices
+            .iter()
+            .enumerate()
+            .map(|(index, device)| (device.device_id.clone(), index))
+            .collect();
+
+        let mut children = vec![Vec::new(); devices.len()];
+        let mut parents = vec![None; devices.len()];
+        let mut roots = Vec::new();
+
+        for (index, device) in devices.iter().enumerate() {
+            let parent = device
+                .parent_device_id
+                .as_ref()
+                .and_then(|parent| indices.get(parent).copied())
+                .filter(|&parent| parent != index);
+
+            match parent {
+                Some(parent) => {
+                    children[parent].push(index);
+                    parents[index] = Some(parent);
+                }
+                None => roots.push(index),
+            }
+        }
+
+        let mut tree = Self { devices, children, parents, roots, indices };
+        tree.break_cycles();
+        tree
+    }
+
+    /// Gets a device by its ID.
+    pub fn get(&self, id: &DeviceId) -> Option<&Device> {
+        self.indices.get(id).map(|&index| &self.devices[index])
+    }
+
+    /// The number of devices in the tree.
+    pub fn len(&self) -> usize { self.devices.len() }
+
+    pub fn is_empty(&self) -> bool { self.devices.is_empty() }
+
+    /// The devices without a parent.
+    pub fn roots(&self) -> impl Iterator<Item = &Device> + '_ { self.devices_at(&self.roots) }
+
+    /// The direct children of a device.
+    pub fn children(&self, id: &DeviceId) -> impl Iterator<Item = &Device> + '_ {
+        let children = self.indices.get(id).map_or(&[][..], |&index| &self.children[index]);
+        self.devices_at(children)
+    }
+
+    /// The parent of a device, if it has one.
+    pub fn parent(&self, id: &DeviceId) -> Option<&Device> {
+        let index = *self.indices.get(id)?;
+        self.parents[index].map(|parent| &self.devices[parent])
+    }
+
+    /// The parent of a device, followed by its parent, and so on up to the root.
+    pub fn ancestors(&self, id: &DeviceId) -> impl Iterator<Item = &Device> + '_ {
+        let mut current = self.indices.get(id).copied();
+        std::iter::from_fn(move || {
+            current = self.parents[current?];
+            current.map(|index| &self.devices[index])
+        })
+    }
+
+    /// The root of the composite device which a device belongs to.
+    pub fn root(&self, id: &DeviceId) -> Option<&Device> {
+        self.ancestors(id).last().or_else(|| self.get(id))
+    }
+
+    /// All of the devices below a device, in depth-first order.
+    pub fn descendants(&self, id: &DeviceId) -> impl Iterator<Item = &Device> + '_ {
+        let children = self.indices.get(id).map_or(&[][..], |&index| &self.children[index]);
+        self.depth_first(children).map(|(_, device)| device)
+    }
+
+    /// The composite device which a device belongs to, starting with its root.
+    pub fn composite(&self, id: &DeviceId) -> Vec<&Device> {
+        match self.root(id) {
+            Some(root) => std::iter::once(root).chain(self.descendants(&root.device_id)).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// The composite devices of the tree, each starting with its root.
+    pub fn composites(&self) -> impl Iterator<Item = Vec<&Device>> + '_ {
+        self.roots().map(move |root| self.composite(&root.device_id))
+    }
+
+    /// All of the devices in depth-first order, with their depth below their root.
+    ///
+    /// ```no_run
+    /// let client = fwupd_dbus::Client::new().unwrap();
+    /// for (depth, device) in client.device_tree().unwrap().iter() {
+    ///     println!("{:indent$}{}", "", device.name, indent = depth * 2);
+    /// }
+    /// ```
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Device)> + '_ {
+        self.depth_first(&self.roots)
+    }
+
+    /// Orders devices of the tree for installing their updates, in the same way as the daemon.
+    ///
+    /// Within a composite device, children are updated before their parent, unless they have
+    /// the `INSTALL_PARENT_FIRST` flag. Devices which are not in the tree are ignored.
+    pub fn install_order<'a, I>(&self, ids: I) -> Vec<&Device>
+    where
+        I: IntoIterator<Item = &'a DeviceId>,
+    {
+        let mut order = vec![0i64; self.devices.len()];
+        let mut pending: Vec<(usize, i64)> = self.roots.iter().map(|&root| (root, 0)).collect();
+
+        while let Some((index, depth)) = pending.pop() {
+            order[index] = depth;
+            for &child in &self.children[index] {
+                let depth = if self.devices[child].flags.contains(DeviceFlags::INSTALL_PARENT_FIRST)
+                {
+                    depth + 1
+                } else {
+                    depth - 1
+                };
+
+                pending.push((child, depth));
+            }
+        }
+
+        let mut seen = vec![false; self.devices.len()];
+        let mut devices: Vec<usize> = ids
+            .into_iter()
+            .filter_map(|id| self.indices.get(id).copied())
+            .filter(|&index| !std::mem::replace(&mut seen[index], true))
+            .collect();
+
+        devices.sort_by_key(|&index| order[index]);
+        devices.into_iter().map(|index| &self.devices[index]).collect()
+    }
+
+    /// Consumes the tree, returning its devices in their original order.
+    pub fn into_devices(self) -> Vec<Device> { self.devices }
+
+    fn devices_at<'a>(&'a self, indices: &'a [usize]) -> impl Iterator<Item = &'a Device> + 'a {
+        indices.iter().map(move |&index| &self.devices[index])
+    }
+
+    fn depth_first<'a>(
+        &'a self,
+        start: &'a [usize],
+    ) -> impl Iterator<Item = (usize, &'a Device)> + 'a {
+        let mut pending: Vec<(usize, usize)> =
+            start.iter().rev().map(|&index| (0, index)).collect();
+
+        std::iter::from_fn(move || {
+            let (depth, index) = pending.pop()?;
+            pending.extend(self.children[index].iter().rev().map(|&child| (depth + 1, child)));
+            Some((depth, &self.devices[index]))
+        })
+    }
+
+    /// Detaches devices whose parents lead back to themselves, making them roots.
+    fn break_cycles(&mut self) {
+        for index in 0..self.devices.len() {
+            let mut current = self.parents[index];
+            let mut steps = 0;
+
+            // Devices which hang from a cycle are left for a device of the cycle to detach.
+            while let Some(parent) = current.filter(|_| steps <= self.devices.len()) {
+                if parent == index {
+                    let parent = self.parents[index].take().expect("cycle without parent");
+                    self.children[parent].retain(|&child| child != index);
+                    self.roots.push(index);
+                    break;
+                }
+
+                current = self.parents[parent];
+                steps += 1;
+            }
+        }
+    }
+}
+
+impl From<Vec<Device>> for DeviceTree {
+    fn from(devices: Vec<Device>) -> Self { Self::new(devices) }
+}
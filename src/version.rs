@@ -0,0 +1,144 @@
+use crate::VersionFormat;
+use std::{borrow::Cow, cmp::Ordering};
+
+/// Compares two versions in the same way as the daemon.
+///
+/// Versions which are a single integer, such as `0x10203`, are first converted into dotted
+/// versions according to the `format`. Each section of a dotted version is compared
+/// numerically, and then by any trailing characters, where `~` sorts before the end of a section.
+/// Versions of the `Plain` format are compared as strings.
+pub fn compare_versions(a: &str, b: &str, format: VersionFormat) -> Ordering {
+    if format == VersionFormat::Plain {
+        return a.cmp(b);
+    }
+
+    let a = parse_from_format(a, format);
+    let b = parse_from_format(b, format);
+
+    let mut sections_a = a.split('.');
+    let mut sections_b = b.split('.');
+
+    loop {
+        let (section_a, section_b) = match (sections_a.next(), sections_b.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a), Some(b)) => (a, b),
+        };
+
+        let (number_a, suffix_a) = split_section(section_a);
+        let (number_b, suffix_b) = split_section(section_b);
+
+        let ordering = number_a.cmp(&number_b).then_with(|| compare_suffixes(suffix_a, suffix_b));
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// Formats a raw integer version, such as the `version_raw` of a device, as the daemon does.
+///
+/// Values which fit into 32 bits are formatted as in [`compare_versions`]. Larger values are
+/// split into sections of 16 bits for `Quad`, 16 + 16 + 32 bits for `Triplet` and 32 bits for
+/// `Pair`, and are otherwise formatted as a number.
+pub fn format_version(raw: u64, format: VersionFormat) -> String {
+    if let Ok(value) = u32::try_from(raw) {
+        return version_from_u32(value, format);
+    }
+
+    let section = |shift: u64| (raw >> shift) & 0xffff;
+
+    match format {
+        VersionFormat::Quad => {
+            format!("{}.{}.{}.{}", section(48), section(32), section(16), section(0))
+        }
+        VersionFormat::Triplet => format!("{}.{}.{}", section(48), section(32), raw & 0xffff_ffff),
+        VersionFormat::Pair => format!("{}.{}", raw >> 32, raw & 0xffff_ffff),
+        _ => raw.to_string(),
+    }
+}
+
+/// Converts an integer version into a dotted version, leaving other versions as they are.
+fn parse_from_format(version: &str, format: VersionFormat) -> Cow<str> {
+    // Already a dotted version
+    if version.contains('.') {
+        return Cow::Borrowed(version);
+    }
+
+    // A date, such as 20230401
+    if version.starts_with("20") && version.len() == 8 {
+        return Cow::Borrowed(version);
+    }
+
+    let value = match version.strip_prefix("0x").or_else(|| version.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => version.parse::<u32>(),
+    };
+
+    match value {
+        Ok(value) if value != 0 => Cow::Owned(version_from_u32(value, format)),
+        _ => Cow::Borrowed(version),
+    }
+}
+
+/// Formats an integer version into a dotted version.
+fn version_from_u32(value: u32, format: VersionFormat) -> String {
+    let byte = |shift: u32| (value >> shift) & 0xff;
+    let bcd = |shift: u32| (byte(shift) >> 4) * 10 + (byte(shift) & 0x0f);
+
+    match format {
+        VersionFormat::Quad => format!("{}.{}.{}.{}", byte(24), byte(16), byte(8), byte(0)),
+        VersionFormat::Triplet => format!("{}.{}.{}", byte(24), byte(16), value & 0xffff),
+        VersionFormat::Pair => format!("{}.{}", value >> 16, value & 0xffff),
+        VersionFormat::Bcd => format!("{}.{}.{}.{}", bcd(24), bcd(16), bcd(8), bcd(0)),
+        VersionFormat::IntelMe => format!(
+            "{}.{}.{}.{}",
+            ((value >> 29) & 0x07) + 0x0b,
+            (value >> 24) & 0x1f,
+            byte(16),
+            value & 0xffff
+        ),
+        VersionFormat::IntelMe2 => format!(
+            "{}.{}.{}.{}",
+            (value >> 28) & 0x0f,
+            (value >> 24) & 0x0f,
+            byte(16),
+            value & 0xffff
+        ),
+        VersionFormat::SurfaceLegacy => {
+            format!("{}.{}.{}", (value >> 22) & 0x3ff, (value >> 10) & 0xfff, value & 0x3ff)
+        }
+        VersionFormat::Surface => {
+            format!("{}.{}.{}", byte(24), (value >> 8) & 0xffff, byte(0))
+        }
+        VersionFormat::DellBios => format!("{}.{}.{}", byte(16), byte(8), byte(0)),
+        VersionFormat::DellBiosMsb => format!("{}.{}.{}", byte(24), byte(16), byte(8)),
+        VersionFormat::Hex => format!("{:#010x}", value),
+        VersionFormat::Plain | VersionFormat::Number | VersionFormat::Unknown(_) => {
+            value.to_string()
+        }
+    }
+}
+
+/// Splits a section of a version into its leading number and the characters after it.
+fn split_section(section: &str) -> (u64, &str) {
+    let end = section.find(|c: char| !c.is_ascii_digit()).unwrap_or(section.len());
+    let (digits, suffix) = section.split_at(end);
+    (digits.parse().unwrap_or(if digits.is_empty() { 0 } else { u64::MAX }), suffix)
+}
+
+/// Compares the characters after the number of a section, where `~` sorts before anything else.
+fn compare_suffixes(a: &str, b: &str) -> Ordering {
+    let mut a = a.bytes();
+    let mut b = b.bytes();
+
+    loop {
+        match (a.next(), b.next()) {
+            (None, None) => return Ordering::Equal,
+            (Some(x), Some(y)) if x == y => continue,
+            (Some(b'~'), _) => return Ordering::Less,
+            (_, Some(b'~')) => return Ordering::Greater,
+            (x, y) => return x.cmp(&y),
+        }
+    }
+}